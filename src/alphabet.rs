@@ -0,0 +1,157 @@
+use std::{fmt, str::FromStr};
+
+/// A set of residues that sequences are made of. Residues
+/// are encoded as dense indices into the alphabet's symbols.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Alphabet {
+    /// Nucleotides of DNA (A, C, G, T).
+    Dna,
+    /// Nucleotides of RNA (A, C, G, U).
+    Rna,
+    /// Amino acids (in the NCBI order used by BLOSUM/PAM matrices).
+    Protein,
+    /// Nucleotides of DNA including the IUPAC ambiguity codes.
+    IupacDna,
+}
+
+/// The error for residues that are not part of an alphabet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidResiduesError {
+    pub alphabet: Alphabet,
+    /// The positions and the characters of the invalid residues.
+    pub residues: Vec<(usize, u8)>,
+}
+
+impl Alphabet {
+    /// The alphabets that are supported.
+    pub const ALL: [Alphabet; 4] = [Alphabet::Dna, Alphabet::Rna, Alphabet::Protein, Alphabet::IupacDna];
+
+    /// The (upper-case) symbols, ordered by their index.
+    pub fn symbols(self) -> &'static [u8] {
+        match self {
+            Alphabet::Dna => b"ACGT",
+            Alphabet::Rna => b"ACGU",
+            Alphabet::Protein => b"ARNDCQEGHILKMFPSTWYVBZX*",
+            Alphabet::IupacDna => b"ACGTRYSWKMBDHVN",
+        }
+    }
+
+    /// Whether the alphabet consists of nucleotides (rather than amino acids).
+    pub fn is_nucleotide(self) -> bool {
+        self != Alphabet::Protein
+    }
+
+    /// The number of residues in the alphabet.
+    pub fn len(self) -> usize {
+        self.symbols().len()
+    }
+
+    /// The name of the alphabet, as accepted by `FromStr`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Alphabet::Dna => "dna",
+            Alphabet::Rna => "rna",
+            Alphabet::Protein => "protein",
+            Alphabet::IupacDna => "iupac-dna",
+        }
+    }
+
+    /// Encodes a single (case-insensitive) residue as its index.
+    /// Rare amino acids (J, O, U) are encoded as unknown (X).
+    pub fn encode_residue(self, residue: u8) -> Option<u8> {
+        let residue = match (self, residue.to_ascii_uppercase()) {
+            (Alphabet::Protein, b'J' | b'O' | b'U') => b'X',
+            (_, r) => r,
+        };
+        self.symbols().iter().position(|&s| s == residue).map(|i| i as u8)
+    }
+
+    /// Decodes an index to its (upper-case) residue.
+    pub fn decode_residue(self, index: u8) -> u8 {
+        self.symbols()[index as usize]
+    }
+
+    /// Encodes the given residues, reporting all invalid ones.
+    pub fn encode(self, raw: &[u8]) -> Result<Vec<u8>, InvalidResiduesError> {
+        let mut encoded = Vec::with_capacity(raw.len());
+        let mut invalid = Vec::new();
+
+        for (i, &r) in raw.iter().enumerate() {
+            match self.encode_residue(r) {
+                Some(index) => encoded.push(index),
+                None => invalid.push((i, r)),
+            }
+        }
+
+        if invalid.is_empty() {
+            Ok(encoded)
+        } else {
+            Err(InvalidResiduesError { alphabet: self, residues: invalid })
+        }
+    }
+
+    /// Decodes the given indices to their residues.
+    pub fn decode(self, encoded: &[u8]) -> Vec<u8> {
+        encoded.iter().map(|&i| self.decode_residue(i)).collect()
+    }
+}
+
+impl FromStr for Alphabet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Alphabet::ALL.into_iter()
+            .find(|a| a.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!(
+                "Unknown alphabet '{}' (supported are: {})",
+                s, Alphabet::ALL.map(|a| a.name()).join(", ")
+            ))
+    }
+}
+
+impl fmt::Display for Alphabet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Display for InvalidResiduesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid residues for alphabet {}: ", self.alphabet)?;
+        for (k, &(i, r)) in self.residues.iter().enumerate() {
+            if k > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "'{}' (at {})", r.escape_ascii(), i)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Alphabet, InvalidResiduesError};
+
+    #[test]
+    fn encodes_residues() {
+        assert_eq!(Alphabet::Dna.encode(b"ACgt"), Ok(vec![0, 1, 2, 3]));
+        assert_eq!(Alphabet::Dna.decode(&[3, 2, 1, 0]), b"TGCA");
+
+        // Rare amino acids are encoded as unknown
+        let x = Alphabet::Protein.encode_residue(b'X');
+        assert_eq!(Alphabet::Protein.encode_residue(b'j'), x);
+        assert_eq!(Alphabet::Protein.encode_residue(b'U'), x);
+    }
+
+    #[test]
+    fn reports_all_invalid_residues() {
+        assert_eq!(
+            Alphabet::Dna.encode(b"ACxGTBb"),
+            Err(InvalidResiduesError { alphabet: Alphabet::Dna, residues: vec![(2, b'x'), (5, b'B'), (6, b'b')] }),
+        );
+        assert_eq!(
+            Alphabet::Dna.encode(b"AC!").unwrap_err().to_string(),
+            "Invalid residues for alphabet dna: '!' (at 2)",
+        );
+    }
+}
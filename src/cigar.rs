@@ -0,0 +1,199 @@
+use std::{fmt, str::FromStr};
+
+use crate::model::{Operation, Sequence, AlignedPair};
+
+/// The flavour of a CIGAR string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CigarStyle {
+    /// Matches and mismatches are both written as 'M'.
+    Basic,
+    /// Matches are written as '=' and mismatches as 'X'.
+    Extended,
+}
+
+/// A CIGAR string, i.e. a run-length encoding of the
+/// operations in an alignment (e.g. `3=1X2I4=`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cigar {
+    entries: Vec<(usize, char)>,
+}
+
+/// An error that occurred while parsing or resolving a CIGAR string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CigarError {
+    pub message: String,
+}
+
+impl Cigar {
+    /// Encodes the given operations in the given style.
+    pub fn from_operations(operations: &[Operation], style: CigarStyle) -> Self {
+        let mut entries: Vec<(usize, char)> = Vec::new();
+        for &op in operations {
+            let c = match (op, style) {
+                (Operation::Match | Operation::Mismatch, CigarStyle::Basic) => 'M',
+                (Operation::Match, CigarStyle::Extended) => '=',
+                (Operation::Mismatch, CigarStyle::Extended) => 'X',
+                (Operation::Insertion, _) => 'I',
+                (Operation::Deletion, _) => 'D',
+            };
+            match entries.last_mut() {
+                Some((count, last)) if *last == c => *count += 1,
+                _ => entries.push((1, c)),
+            }
+        }
+        Self { entries }
+    }
+
+    /// Decodes the operations, starting at the given indices into the
+    /// database and the query. Since 'M' does not tell matches and
+    /// mismatches apart, this compares the aligned residues.
+    pub fn to_operations(&self, database: &Sequence, database_start: usize, query: &Sequence, query_start: usize) -> Result<Vec<Operation>, CigarError> {
+        let mut operations = Vec::new();
+        let mut i = database_start;
+        let mut j = query_start;
+
+        for &(count, c) in &self.entries {
+            for _ in 0..count {
+                let op = match c {
+                    'I' => Operation::Insertion,
+                    'D' => Operation::Deletion,
+                    _ if i >= database.len() || j >= query.len() => {
+                        return Err(CigarError::new(format!("'{}' exceeds the sequences (at {}/{})", c, i, j)));
+                    },
+                    'M' | '=' | 'X' => match (c, database[i] == query[j]) {
+                        ('M' | '=', true) => Operation::Match,
+                        ('M' | 'X', false) => Operation::Mismatch,
+                        _ => return Err(CigarError::new(format!("'{}' does not fit the residues (at {}/{})", c, i, j))),
+                    },
+                    _ => unreachable!(),
+                };
+
+                if op.consumes_database() {
+                    i += 1;
+                }
+                if op.consumes_query() {
+                    j += 1;
+                }
+                operations.push(op);
+            }
+        }
+
+        if i > database.len() || j > query.len() {
+            return Err(CigarError::new(format!("Operations exceed the sequences (ending at {}/{})", i, j)));
+        }
+
+        Ok(operations)
+    }
+}
+
+impl CigarError {
+    fn new(message: String) -> Self {
+        Self { message }
+    }
+}
+
+impl<'a> AlignedPair<'a> {
+    /// Encodes the alignment's operations as a CIGAR string.
+    pub fn cigar(&self, style: CigarStyle) -> Cigar {
+        Cigar::from_operations(&self.operations, style)
+    }
+
+    /// Creates an alignment from a CIGAR string that starts at
+    /// the given indices in the database and query.
+    pub fn from_cigar(database: &'a Sequence, query: &'a Sequence, database_start: usize, query_start: usize, cigar: &Cigar, score: i32) -> Result<Self, CigarError> {
+        let operations = cigar.to_operations(database, database_start, query, query_start)?;
+        Ok(Self::new(database, query, database_start, query_start, operations, score))
+    }
+}
+
+impl FromStr for Cigar {
+    type Err = CigarError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut entries = Vec::new();
+        let mut count = String::new();
+
+        for c in s.chars() {
+            match c {
+                '0'..='9' => count.push(c),
+                'M' | '=' | 'X' | 'I' | 'D' => {
+                    let n = count.parse::<usize>()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| CigarError::new(format!("Invalid count '{}' for '{}'", count, c)))?;
+                    entries.push((n, c));
+                    count.clear();
+                },
+                _ => return Err(CigarError::new(format!("Unsupported operation '{}'", c))),
+            }
+        }
+
+        if !count.is_empty() {
+            return Err(CigarError::new(format!("Missing operation after '{}'", count)));
+        }
+
+        Ok(Self { entries })
+    }
+}
+
+impl fmt::Display for Cigar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (count, c) in &self.entries {
+            write!(f, "{}{}", count, c)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for CigarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{alphabet::Alphabet, model::{AlignedPair, Operation, Sequence}};
+
+    use super::{Cigar, CigarStyle};
+
+    fn sequences() -> (Sequence, Sequence) {
+        let database = Sequence::parse("Database", Alphabet::Dna, b"TACGGA").unwrap();
+        let query = Sequence::parse("Query", Alphabet::Dna, b"ACTCA").unwrap();
+        (database, query)
+    }
+
+    #[test]
+    fn formats_and_parses() {
+        let (database, query) = sequences();
+        let operations = vec![Operation::Match, Operation::Match, Operation::Mismatch, Operation::Insertion, Operation::Deletion, Operation::Match];
+        let aligned = AlignedPair::new(&database, &query, 1, 0, operations, 7);
+
+        assert_eq!(aligned.cigar(CigarStyle::Basic).to_string(), "3M1I1D1M");
+        assert_eq!(aligned.cigar(CigarStyle::Extended).to_string(), "2=1X1I1D1=");
+
+        for style in [CigarStyle::Basic, CigarStyle::Extended] {
+            let cigar = aligned.cigar(style);
+            let parsed: Cigar = cigar.to_string().parse().unwrap();
+            assert_eq!(parsed, cigar);
+            assert_eq!(AlignedPair::from_cigar(&database, &query, 1, 0, &parsed, 7).unwrap(), aligned);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_strings() {
+        for s in ["3", "0M", "3Q", "M", "2M-1I"] {
+            assert!(s.parse::<Cigar>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn rejects_operations_that_do_not_fit() {
+        let (database, query) = sequences();
+        // Exceeding the sequences and claiming a match for a mismatch (and vice versa)
+        for s in ["9M", "6I", "3=", "1X"] {
+            let cigar: Cigar = s.parse().unwrap();
+            assert!(AlignedPair::from_cigar(&database, &query, 1, 0, &cigar, 0).is_err(), "{}", s);
+        }
+    }
+}
@@ -1,5 +0,0 @@
-// Constant parameters for the Smith-Waterman algorithm.
-
-pub const G_INIT: i16 = 2;
-pub const G_EXT: i16 = 2;
-pub const WEIGHT_IF_EQ: i16 = 3;
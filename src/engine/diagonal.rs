@@ -1,45 +1,64 @@
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};
 use rayon::prelude::*;
 
-use crate::{model::{Sequence, AlignedPair, AlignedSequence}, metrics::Metrics, utils::UnsafeSlice};
+use crate::{model::{Sequence, AlignedPair, AlignmentScore}, metrics::Metrics, error::Error, utils::{Pool, UnsafeSlice}};
 
-use super::{Engine, G_INIT, G_EXT, WEIGHT_IF_EQ};
+use super::{AlignmentMode, Direction, Engine, Origin, Score, ScoringScheme, TracebackCell, Workspace, matrix_size, resized, traceback};
 
 /// An engine that computes alignments using the
 /// Smith-Waterman-Algorithm with parallelized
 /// diagonals on the CPU.
-pub struct DiagonalEngine;
+pub struct DiagonalEngine {
+    scoring: ScoringScheme,
+    mode: AlignmentMode,
+    workspaces: Pool,
+}
 
 impl DiagonalEngine {
-    fn weight(d: u8, q: u8) -> i16 {
-        if d == q { WEIGHT_IF_EQ } else { -WEIGHT_IF_EQ }
+    pub fn new(scoring: ScoringScheme, mode: AlignmentMode) -> Self {
+        Self { scoring, mode, workspaces: Pool::new() }
     }
-}
 
-impl Engine for DiagonalEngine {
-    fn name(&self) -> String {
-        "Diagonal (CPU)".to_owned()
-    }
+    /// Aligns the given two sequences using the given score type,
+    /// or returns none if any value of h saturates.
+    fn align_with<'a, S: Score>(&self, database: &'a Sequence, query: &'a Sequence) -> Result<Option<AlignedPair<'a>>, Error> {
+        if !S::fits(&self.scoring) {
+            return Ok(None);
+        }
 
-    fn align<'a>(&self, database: &'a Sequence, query: &'a Sequence, metrics: &Arc<Mutex<Metrics>>) -> AlignedPair<'a> {
         let n = database.len();
         let m = query.len();
         let height = n + 1;
         let width = m + 1;
-        let size = height * width;
+        let size = matrix_size(n, m)?;
 
-        // Create scoring matrix h, helper matrices e and f and a
-        // helper matrix p that tracks the previous index
+        // Create scoring matrix h, helper matrices e and f, a
+        // helper matrix p that tracks the direction the value came
+        // from and whether e and f extend a gap (rather than opening one)
 
-        let mut h = vec![0; size];
-        let mut e = vec![0; size];
-        let mut f = vec![0; size];
-        let mut p = vec![0; size];
+        let mut workspace = self.workspaces.take(Workspace::<S>::default);
+        let workspace = &mut *workspace;
+        let h = resized(&mut workspace.h, size)?;
+        let e = resized(&mut workspace.e, size)?;
+        let f = resized(&mut workspace.f, size)?;
+        let p = resized(&mut workspace.p, size)?;
 
-        let ph = UnsafeSlice::new(&mut h);
-        let pe = UnsafeSlice::new(&mut e);
-        let pf = UnsafeSlice::new(&mut f);
-        let pp = UnsafeSlice::new(&mut p);
+        // Initialize the borders (the leftmost column and the topmost row)
+
+        for i in 0..height {
+            (h[i * width], e[i * width], f[i * width]) = self.mode.border(&self.scoring, i, 0);
+        }
+        for j in 1..width {
+            (h[j], e[j], f[j]) = self.mode.border(&self.scoring, 0, j);
+        }
+
+        let uh = UnsafeSlice::new(h);
+        let ue = UnsafeSlice::new(e);
+        let uf = UnsafeSlice::new(f);
+        let up = UnsafeSlice::new(p);
+
+        let gap_open = S::saturating_from(self.scoring.gap_open as i32);
+        let gap_extend = S::saturating_from(self.scoring.gap_extend as i32);
 
         // Perform scoring stage (dynamic programming-style)
         // We iterate over the diagonals and parallelize over
@@ -66,46 +85,205 @@ impl Engine for DiagonalEngine {
 
                 unsafe {
                     // Compute helper values
-                    pe.write(here, (pe.read(left) - G_EXT).max(ph.read(left) - G_INIT));
-                    pf.write(here, (pf.read(above) - G_EXT).max(ph.read(above) - G_INIT));
+                    let e_extend = ue.read(left).saturating_sub(gap_extend);
+                    let e_open = uh.read(left).saturating_sub(gap_open);
+                    let f_extend = uf.read(above).saturating_sub(gap_extend);
+                    let f_open = uh.read(above).saturating_sub(gap_open);
 
-                    // Compute value and remember the index the maximum came from
+                    ue.write(here, e_extend.max(e_open));
+                    uf.write(here, f_extend.max(f_open));
+
+                    // Compute value and remember the direction the maximum came from
                     // (we need this later for the traceback phase)
                     let (max_origin, max_value) = [
-                        (0,          0),
-                        (above_left, ph.read(above_left) + Self::weight(database[i - 1], query[j - 1])),
-                        (left,       pe.read(here)),
-                        (above,      pf.read(here)),
+                        (Origin::Start,    self.mode.min_score()),
+                        (Origin::Diagonal, uh.read(above_left).saturating_add(S::saturating_from(self.scoring.weight(database[i - 1], query[j - 1]) as i32))),
+                        (Origin::Left,     ue.read(here)),
+                        (Origin::Above,    uf.read(here)),
                     ].into_iter().max_by_key(|&(_, x)| x).unwrap();
                     
-                    ph.write(here, max_value);
-                    pp.write(here, max_origin);
+                    uh.write(here, max_value);
+                    up.write(here, Direction::new(max_origin, e_extend > e_open, f_extend > f_open));
                 }
             });
         }
 
-        metrics.lock().unwrap().record_cell_updates(4 * size);
+        // The values (and thus the traceback) are only exact if none of them saturated
+
+        if h.iter().any(|x| x.is_saturated()) {
+            return Ok(None);
+        }
 
         // Perform traceback stage (using the previously computed scoring matrix h)
 
-        let mut i = (0..size).max_by_key(|&i| h[i]).unwrap();
-        let mut database_indices = Vec::new();
-        let mut query_indices = Vec::new();
+        let end = match self.mode.free_end_gaps() {
+            Some(gaps) => gaps.end(n, m, |i, j| h[i * width + j]),
+            None => {
+                let end = (0..size).max_by_key(|&i| h[i]).unwrap();
+                (end / width, end % width)
+            },
+        };
+        Ok(Some(traceback(database, query, self.mode, end, |i, j| i * width + j, |i| TracebackCell {
+            score: h[i].into(),
+            direction: p[i],
+        })))
+    }
+}
 
-        while i > 0 && h[i] > 0 {
-            database_indices.push((i / width) - 1);
-            query_indices.push((i % width) - 1);
-            i = p[i];
-        }
+impl Engine for DiagonalEngine {
+    fn name(&self) -> String {
+        "Diagonal (CPU)".to_owned()
+    }
 
-        database_indices.reverse();
-        query_indices.reverse();
+    fn align<'a>(&self, database: &'a Sequence, query: &'a Sequence, metrics: &Arc<Mutex<Metrics>>) -> Result<AlignedPair<'a>, Error> {
+        // Start with 8-bit scores and widen them if the alignment saturates
+        let aligned = self.align_with::<i8>(database, query).transpose()
+            .or_else(|| self.align_with::<i16>(database, query).transpose())
+            .or_else(|| self.align_with::<i32>(database, query).transpose())
+            .unwrap_or(Err(Error::ScoreOverflow))?;
 
+        metrics.lock().unwrap().record_cell_updates(4 * (database.len() + 1) * (query.len() + 1));
         metrics.lock().unwrap().record_sequence_pair();
 
-        AlignedPair::new(
-            AlignedSequence::new(database, database_indices),
-            AlignedSequence::new(query, query_indices),
-        )
+        Ok(aligned)
     }
+
+    fn score(&self, database: &Sequence, query: &Sequence, metrics: &Arc<Mutex<Metrics>>) -> Result<AlignmentScore, Error> {
+        let score = score_diagonals(&self.scoring, self.mode, database, query)?;
+
+        metrics.lock().unwrap().record_cell_updates(4 * (database.len() + 1) * (query.len() + 1));
+        metrics.lock().unwrap().record_sequence_pair();
+
+        Ok(score)
+    }
+}
+
+/// Computes the score of the alignment and where it ends by iterating over
+/// the diagonals in parallel. Since we only keep the last diagonals, this
+/// needs linear memory (and is thus the same for all diagonal engines).
+pub fn score_diagonals(scoring: &ScoringScheme, mode: AlignmentMode, database: &Sequence, query: &Sequence) -> Result<AlignmentScore, Error> {
+    score_diagonals_with::<i8>(scoring, mode, database, query)
+        .or_else(|| score_diagonals_with::<i16>(scoring, mode, database, query))
+        .or_else(|| score_diagonals_with::<i32>(scoring, mode, database, query))
+        .ok_or(Error::ScoreOverflow)
+}
+
+/// Computes the score like `score_diagonals` using the given
+/// score type, or returns none if any value of h saturates.
+fn score_diagonals_with<S: Score>(scoring: &ScoringScheme, mode: AlignmentMode, database: &Sequence, query: &Sequence) -> Option<AlignmentScore> {
+    if !S::fits(scoring) {
+        return None;
+    }
+
+    let n = database.len();
+    let m = query.len();
+    let height = n + 1;
+    let width = m + 1;
+
+    // Compute the borders (the topmost row and the leftmost column) of h.
+    // The borders of e and f are always negative infinity.
+
+    let border_row: Vec<S> = (0..width).map(|j| mode.border(scoring, 0, j).0).collect();
+    let border_column: Vec<S> = (0..height).map(|i| mode.border(scoring, i, 0).0).collect();
+
+    // Create the current and the last two diagonals of h and the
+    // current and the last diagonal of e and f. We index all of
+    // them by i, thus the neighbors of cell i in the current
+    // diagonal are the cells i - 1 (above) and i (left) in the
+    // last diagonal and i - 1 (above left) in the one before.
+
+    let mut h_previous_previous = vec![S::ZERO; height];
+    let mut h_previous = vec![S::ZERO; height];
+    let mut h_current = vec![S::ZERO; height];
+    let mut e_previous = vec![S::ZERO; height];
+    let mut e_current = vec![S::ZERO; height];
+    let mut f_previous = vec![S::ZERO; height];
+    let mut f_current = vec![S::ZERO; height];
+
+    // To find the end of the alignment, we track the maximum
+    // of each row and the last row and column.
+
+    let mut row_maxima: Vec<_> = border_column.iter().map(|&x| (x, 0)).collect();
+    let mut last_row = border_row.clone();
+    let mut last_column = border_column.clone();
+
+    row_maxima[0] = (0..width).map(|j| (border_row[j], j)).max_by_key(|&(x, _)| x).unwrap();
+    last_row[0] = border_column[n];
+    last_column[0] = border_row[m];
+
+    // Since the cells of a diagonal are computed in parallel,
+    // any of them may flag that the values saturated.
+
+    let saturated = AtomicBool::new(border_row.iter().chain(&border_column).any(|x| x.is_saturated()));
+    let gap_open = S::saturating_from(scoring.gap_open as i32);
+    let gap_extend = S::saturating_from(scoring.gap_extend as i32);
+
+    // Perform scoring stage (dynamic programming-style)
+
+    for k in 2..=(n + m) {
+        // The lower and upper bounds for the diagonal('s j index), as in the engine above.
+        let lower = (k as isize - height as isize + 1).max(1) as usize;
+        let upper = k.min(width);
+
+        let uh = UnsafeSlice::new(&mut h_current);
+        let ue = UnsafeSlice::new(&mut e_current);
+        let uf = UnsafeSlice::new(&mut f_current);
+        let urow_maxima = UnsafeSlice::new(&mut row_maxima);
+        let ulast_row = UnsafeSlice::new(&mut last_row);
+        let ulast_column = UnsafeSlice::new(&mut last_column);
+
+        // Iterate the diagonal in parallel
+        (lower..upper).into_par_iter().for_each(|j| {
+            let i = k - j;
+
+            // Fetch the neighboring cells, falling back to the borders
+            let h_above = if i == 1 { border_row[j] } else { h_previous[i - 1] };
+            let h_left = if j == 1 { border_column[i] } else { h_previous[i] };
+            let h_above_left = if i == 1 { border_row[j - 1] } else if j == 1 { border_column[i - 1] } else { h_previous_previous[i - 1] };
+            let e_left = if j == 1 { S::NEGATIVE_INFINITY } else { e_previous[i] };
+            let f_above = if i == 1 { S::NEGATIVE_INFINITY } else { f_previous[i - 1] };
+
+            // Compute helper values and value
+            let e_here = e_left.saturating_sub(gap_extend).max(h_left.saturating_sub(gap_open));
+            let f_here = f_above.saturating_sub(gap_extend).max(h_above.saturating_sub(gap_open));
+            let h_here = h_above_left.saturating_add(S::saturating_from(scoring.weight(database[i - 1], query[j - 1]) as i32))
+                .max(e_here)
+                .max(f_here)
+                .max(mode.min_score());
+
+            if h_here.is_saturated() {
+                saturated.store(true, Ordering::Relaxed);
+            }
+
+            unsafe {
+                uh.write(i, h_here);
+                ue.write(i, e_here);
+                uf.write(i, f_here);
+
+                // Since every row is visited once per diagonal (from left to right),
+                // we can track the maxima without synchronization.
+                if h_here >= urow_maxima.read(i).0 {
+                    urow_maxima.write(i, (h_here, j));
+                }
+                if i == n {
+                    ulast_row.write(j, h_here);
+                }
+                if j == m {
+                    ulast_column.write(i, h_here);
+                }
+            }
+        });
+
+        // Rotate the diagonals
+        std::mem::swap(&mut h_previous_previous, &mut h_previous);
+        std::mem::swap(&mut h_previous, &mut h_current);
+        std::mem::swap(&mut e_previous, &mut e_current);
+        std::mem::swap(&mut f_previous, &mut f_current);
+    }
+
+    if saturated.into_inner() {
+        return None;
+    }
+
+    Some(mode.find_end(&row_maxima, &last_row, &last_column))
 }
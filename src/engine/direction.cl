@@ -0,0 +1,13 @@
+// The traceback data of a cell, packed into a single byte (see the
+// Direction type on the host): The lowest two bits hold the origin
+// of h and the next two bits whether e and f extend a gap.
+#define ORIGIN_START 0
+#define ORIGIN_DIAGONAL 1
+#define ORIGIN_LEFT 2
+#define ORIGIN_ABOVE 3
+
+#define ORIGIN_MASK 3
+#define E_EXTENDED (1 << 2)
+#define F_EXTENDED (1 << 3)
+
+#define DIRECTION(origin, e_extended, f_extended) ((uchar) ((origin) | ((e_extended) << 2) | ((f_extended) << 3)))
@@ -0,0 +1,306 @@
+use std::{iter, sync::{Arc, Mutex}};
+
+use crate::{model::{Sequence, AlignedPair, AlignmentScore, Operation}, metrics::Metrics, error::Error};
+
+use super::{AlignmentMode, Engine, ScoringScheme, score_rows};
+
+/// An engine that computes alignments in linear space on the
+/// CPU using Hirschberg's divide-and-conquer approach (more
+/// specifically its variant for affine gap penalties by
+/// Myers and Miller).
+pub struct HirschbergEngine {
+    scoring: ScoringScheme,
+    mode: AlignmentMode,
+}
+
+impl HirschbergEngine {
+    pub fn new(scoring: ScoringScheme, mode: AlignmentMode) -> Self {
+        Self { scoring, mode }
+    }
+
+    /// The gap penalties in the form used by Myers and Miller, i.e.
+    /// a gap of length k costs g + h * k.
+    fn gap_penalties(&self) -> (i32, i32) {
+        let gap_open = self.scoring.gap_open as i32;
+        let gap_extend = self.scoring.gap_extend as i32;
+        (gap_open - gap_extend, gap_extend)
+    }
+
+    /// The (negative) score of a gap with the given length.
+    fn gap(&self, length: usize) -> i32 {
+        let (g, h) = self.gap_penalties();
+        if length == 0 { 0 } else { -(g + h * length as i32) }
+    }
+
+    /// Globally aligns a and b, returning the last row of h and the last
+    /// row of the helper matrix for alignments that end with a deletion.
+    /// A deletion at the start costs tb (rather than g) to open, since
+    /// it might continue a gap. The visitor is called with every cell.
+    fn forward(&self, a: &[u8], b: &[u8], tb: i32, mut visit: impl FnMut(usize, usize, i32)) -> (Vec<i32>, Vec<i32>) {
+        let (g, h) = self.gap_penalties();
+        let n = b.len();
+
+        // Initialize the topmost row, where only insertions are possible
+        let mut cc: Vec<_> = (0..=n).map(|j| self.gap(j)).collect();
+        let mut dd: Vec<_> = cc.iter().map(|&c| c - g).collect();
+
+        for (j, &c) in cc.iter().enumerate() {
+            visit(0, j, c);
+        }
+
+        for i in 1..=a.len() {
+            // The leftmost column consists of deletions only
+            let mut s = cc[0];
+            let mut c = -(tb + h * i as i32);
+            let mut e = c - g;
+            cc[0] = c;
+            visit(i, 0, c);
+
+            for j in 1..=n {
+                // At this point, c holds the value to the left, s the one above
+                // left and cc/dd the ones above (from the previous row).
+                e = (e - h).max(c - g - h);
+                let d = (dd[j] - h).max(cc[j] - g - h);
+                c = (s + self.scoring.weight(a[i - 1], b[j - 1]) as i32).max(e).max(d);
+                s = cc[j];
+                cc[j] = c;
+                dd[j] = d;
+                visit(i, j, c);
+            }
+        }
+
+        dd[0] = cc[0];
+        (cc, dd)
+    }
+
+    /// Globally aligns a and b in linear space, pushing the operations.
+    /// The costs tb and te for opening a deletion at the start or end
+    /// are 0 if the deletion continues a gap outside of a and b.
+    fn diff(&self, a: &[u8], b: &[u8], tb: i32, te: i32, operations: &mut Vec<Operation>) {
+        let (g, h) = self.gap_penalties();
+        let m = a.len();
+        let n = b.len();
+
+        if n == 0 {
+            operations.extend(iter::repeat_n(Operation::Deletion, m));
+            return;
+        }
+
+        if m == 0 {
+            operations.extend(iter::repeat_n(Operation::Insertion, n));
+            return;
+        }
+
+        if m == 1 {
+            // Either delete the single residue (next to the end that
+            // is cheaper to open a gap at) and insert all of b or align
+            // the residue with a residue of b and insert the others.
+            let mut best = -(tb.min(te) + h) + self.gap(n);
+            let mut best_j = None;
+
+            for (j, &residue) in b.iter().enumerate() {
+                let score = self.gap(j) + self.scoring.weight(a[0], residue) as i32 + self.gap(n - 1 - j);
+                if score > best {
+                    best = score;
+                    best_j = Some(j);
+                }
+            }
+
+            let insertions = |count| iter::repeat_n(Operation::Insertion, count);
+            match best_j {
+                Some(j) => {
+                    let op = if a[0] == b[j] { Operation::Match } else { Operation::Mismatch };
+                    operations.extend(insertions(j).chain([op]).chain(insertions(n - 1 - j)));
+                },
+                None if tb <= te => operations.extend(iter::once(Operation::Deletion).chain(insertions(n))),
+                None => operations.extend(insertions(n).chain([Operation::Deletion])),
+            }
+            return;
+        }
+
+        // Compute the last rows for the upper half (forwards) and
+        // for the lower half (backwards, i.e. on the reversed sequences)
+        let mid = m / 2;
+        let reversed_a: Vec<_> = a[mid..].iter().rev().copied().collect();
+        let reversed_b: Vec<_> = b.iter().rev().copied().collect();
+        let (cc, dd) = self.forward(&a[..mid], b, tb, |_, _, _| {});
+        let (rr, ss) = self.forward(&reversed_a, &reversed_b, te, |_, _, _| {});
+
+        // Find the column where the optimal alignment crosses the middle,
+        // either by a cell of h (type 1) or by a deletion spanning both
+        // halves (type 2, where we only need to open the gap once).
+        let mut best = (cc[0] + rr[n], 0, false);
+
+        for j in 0..=n {
+            let score = cc[j] + rr[n - j];
+            if score > best.0 {
+                best = (score, j, false);
+            }
+        }
+
+        for j in 0..=n {
+            let score = dd[j] + ss[n - j] + g;
+            if score > best.0 {
+                best = (score, j, true);
+            }
+        }
+
+        // Recurse into the two halves
+        match best {
+            (_, j, false) => {
+                self.diff(&a[..mid], &b[..j], tb, g, operations);
+                self.diff(&a[mid..], &b[j..], g, te, operations);
+            },
+            (_, j, true) => {
+                self.diff(&a[..(mid - 1)], &b[..j], tb, 0, operations);
+                operations.extend([Operation::Deletion, Operation::Deletion]);
+                self.diff(&a[(mid + 1)..], &b[j..], 0, te, operations);
+            },
+        }
+    }
+
+    /// Finds where the alignment with the given end starts by aligning the
+    /// reversed prefixes (anchored at the end). Local alignments may start
+    /// anywhere, whereas semi-global alignments start at the border.
+    fn find_start(&self, database: &[u8], query: &[u8]) -> (usize, usize) {
+        let (g, _) = self.gap_penalties();
+        let n = database.len();
+        let m = query.len();
+        let reversed_database: Vec<_> = database.iter().rev().copied().collect();
+        let reversed_query: Vec<_> = query.iter().rev().copied().collect();
+
+        let gaps = self.mode.free_end_gaps();
+        let is_start = |i: usize, j: usize| match gaps {
+            Some(gaps) => (i == n && (j == m || gaps.database_leading)) || (j == m && gaps.query_leading),
+            None => true,
+        };
+
+        let mut best = (i32::MIN, n, m);
+        self.forward(&reversed_database, &reversed_query, g, |i, j, score| {
+            if is_start(i, j) && score > best.0 {
+                best = (score, i, j);
+            }
+        });
+
+        (n - best.1, m - best.2)
+    }
+}
+
+impl Engine for HirschbergEngine {
+    fn name(&self) -> String {
+        "Hirschberg (CPU)".to_owned()
+    }
+
+    fn align<'a>(&self, database: &'a Sequence, query: &'a Sequence, metrics: &Arc<Mutex<Metrics>>) -> Result<AlignedPair<'a>, Error> {
+        let (g, _) = self.gap_penalties();
+
+        // Find the score and the end of the alignment (in linear space)
+        let AlignmentScore { score, database_end, query_end } = score_rows(&self.scoring, self.mode, database, query)?;
+
+        // Find the start of the alignment, unless it starts at the origin
+        let (database_start, query_start) = match self.mode.free_end_gaps() {
+            Some(gaps) if !gaps.database_leading && !gaps.query_leading => (0, 0),
+            _ => self.find_start(&database.residues[..database_end], &query.residues[..query_end]),
+        };
+
+        // Globally align the subsequences in between (in linear space)
+        let mut operations = Vec::new();
+        self.diff(
+            &database.residues[database_start..database_end],
+            &query.residues[query_start..query_end],
+            g, g, &mut operations
+        );
+
+        metrics.lock().unwrap().record_cell_updates(4 * (database.len() + 1) * (query.len() + 1));
+        metrics.lock().unwrap().record_sequence_pair();
+
+        Ok(AlignedPair::new(database, query, database_start, query_start, operations, score))
+    }
+
+    fn score(&self, database: &Sequence, query: &Sequence, metrics: &Arc<Mutex<Metrics>>) -> Result<AlignmentScore, Error> {
+        let score = score_rows(&self.scoring, self.mode, database, query)?;
+
+        metrics.lock().unwrap().record_cell_updates(4 * (database.len() + 1) * (query.len() + 1));
+        metrics.lock().unwrap().record_sequence_pair();
+
+        Ok(score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{alphabet::Alphabet, metrics::Metrics, model::Sequence};
+    use crate::engine::{AlignmentMode, Engine, NaiveEngine, ScoringScheme, SubstitutionMatrix};
+    use crate::engine::testing::{MODES, Rng, assert_matches_naive, rescore};
+
+    use super::HirschbergEngine;
+
+    /// Asserts that the operations of the Hirschberg engine's alignment score
+    /// what the engine claims (which it computes without the operations) and that
+    /// the alignment ends like the one traced back by the naive engine. Unless
+    /// there are several optimal alignments, it also has to start like it.
+    fn assert_matches_naive_traceback(scoring: &ScoringScheme, mode: AlignmentMode, database: &Sequence, query: &Sequence, unique: bool) {
+        let metrics = Arc::new(Mutex::new(Metrics::new()));
+        let aligned = HirschbergEngine::new(scoring.clone(), mode).align(database, query, &metrics).unwrap();
+        let expected = NaiveEngine::new(scoring.clone(), mode).align(database, query, &metrics).unwrap();
+
+        assert_eq!(rescore(scoring, &aligned), aligned.score, "{} {}", mode, aligned);
+        assert_eq!(aligned.score, expected.score, "{} {}", mode, aligned);
+        assert_eq!((aligned.database.end, aligned.query.end), (expected.database.end, expected.query.end), "{} {} {}", mode, aligned, expected);
+        if unique {
+            assert_eq!((aligned.database.start, aligned.query.start), (expected.database.start, expected.query.start), "{} {} {}", mode, aligned, expected);
+        }
+    }
+
+    fn assert_all_modes_match_naive_traceback(database: &[u8], query: &[u8]) {
+        let scoring = ScoringScheme::new(6, 1, SubstitutionMatrix::uniform(Alphabet::Dna, 2, -3));
+        let database = Sequence::parse("Database", Alphabet::Dna, database).unwrap();
+        let query = Sequence::parse("Query", Alphabet::Dna, query).unwrap();
+        for mode in MODES {
+            assert_matches_naive_traceback(&scoring, mode, &database, &query, true);
+            assert_matches_naive_traceback(&scoring, mode, &query, &database, true);
+        }
+    }
+
+    #[test]
+    fn matches_naive() {
+        assert_matches_naive(HirschbergEngine::new);
+    }
+
+    #[test]
+    fn crosses_the_middle_with_a_deletion() {
+        // The middle row of the database lies in the gap (type 2)
+        assert_all_modes_match_naive_traceback(b"ACGTCATTTTTTTTTTTTGATCCA", b"ACGTCAGATCCA");
+    }
+
+    #[test]
+    fn aligns_single_residues() {
+        // The base case of a single residue, which is either aligned or deleted
+        assert_all_modes_match_naive_traceback(b"A", b"CCACC");
+        assert_all_modes_match_naive_traceback(b"A", b"CCCC");
+        assert_all_modes_match_naive_traceback(b"A", b"");
+        assert_all_modes_match_naive_traceback(b"GACGT", b"GCGT");
+    }
+
+    #[test]
+    fn continues_gaps_at_the_ends() {
+        // The gaps at both ends span several of the halves
+        assert_all_modes_match_naive_traceback(b"TTTTTACGTCAGGTCTTTTT", b"ACGTCAGGTC");
+        assert_all_modes_match_naive_traceback(b"ACGTCAGGTCTTTTTTTTGATCCATGCA", b"ACGTCAGGTCGATCCATGCA");
+    }
+
+    #[test]
+    fn matches_naive_traceback_on_random_pairs() {
+        let mut rng = Rng::new(7);
+        for _ in 0..200 {
+            let database = rng.sequence(40);
+            let query = rng.sequence(30);
+            let scoring = ScoringScheme::new(6, 1, SubstitutionMatrix::uniform(Alphabet::Dna, 2, -3));
+            for mode in MODES {
+                assert_matches_naive_traceback(&scoring, mode, &database, &query, false);
+            }
+        }
+    }
+}
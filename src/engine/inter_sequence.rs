@@ -0,0 +1,364 @@
+use std::sync::{Arc, Mutex};
+use rayon::prelude::*;
+
+use crate::{model::{Sequence, AlignedPair, AlignmentScore}, metrics::Metrics, error::Error};
+
+use super::{AlignmentMode, Engine, InstructionSet, Score, ScoringScheme, SimdScore, StripedEngine, Vector, Scalar, score_rows};
+
+/// An engine that aligns many queries against the same database
+/// sequence at once (similar to SWIPE) on the CPU. Every lane of
+/// the SIMD vectors holds a different query, thus the lanes are
+/// independent of each other and no lazy loop is needed. Once a
+/// query is done, its lane is refilled with the next query.
+///
+/// The queries are first scored with 8 bits per lane. Those whose
+/// values saturate are then rescored with 16 and, if needed, 32 bits.
+pub struct InterSequenceEngine {
+    scoring: ScoringScheme,
+    mode: AlignmentMode,
+    instruction_set: InstructionSet,
+    /// The engine performing the traceback for the hits.
+    striped: StripedEngine,
+}
+
+/// The result of aligning a query during a database search.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SearchResult<'a> {
+    /// The score of the alignment and where it ends.
+    pub score: AlignmentScore,
+    /// The alignment, if the query is a hit (and the traceback was thus performed).
+    pub alignment: Option<AlignedPair<'a>>,
+}
+
+impl InterSequenceEngine {
+    /// The number of queries each (parallel) task processes, in multiples of the (8-bit) lanes.
+    const CHUNK_VECTORS: usize = 64;
+
+    /// Creates the engine, which fails if the CPU does not support the instruction set.
+    pub fn new(scoring: ScoringScheme, mode: AlignmentMode, instruction_set: InstructionSet) -> Result<Self, Error> {
+        let striped = StripedEngine::new(scoring.clone(), mode, instruction_set)?;
+        Ok(Self { scoring, mode, instruction_set, striped })
+    }
+
+    /// Aligns all queries against the database, returning the results (or why
+    /// they failed) in the order of the queries. If a minimum score is given, the
+    /// queries scoring at least that much are hits, which we additionally trace back.
+    pub fn search<'a>(&self, database: &'a Sequence, queries: &'a [Sequence], min_score: Option<i32>, metrics: &Arc<Mutex<Metrics>>) -> Vec<Result<SearchResult<'a>, Error>> {
+        let chunk_size = Self::CHUNK_VECTORS * self.instruction_set.lanes::<i8>();
+
+        queries.par_chunks(chunk_size).flat_map_iter(|chunk| {
+            let scores = self.score_chunk(database, chunk);
+
+            let cell_updates = chunk.iter().map(|query| 4 * (database.len() + 1) * (query.len() + 1)).sum();
+            metrics.lock().unwrap().record_cell_updates(cell_updates);
+
+            // The cells of the hits were recorded above already,
+            // thus we only record the pairs for their traceback
+            chunk.iter().zip(scores).map(|(query, score)| {
+                let score = score?;
+                let alignment = match min_score {
+                    Some(min_score) if score.score >= min_score => Some(self.striped.align_pair(database, query)?),
+                    _ => None,
+                };
+                metrics.lock().unwrap().record_sequence_pair();
+                Ok(SearchResult { score, alignment })
+            }).collect::<Vec<_>>()
+        }).collect()
+    }
+
+    /// Computes the scores of the given queries' alignments against
+    /// the database, starting with 8-bit scores and rescoring the
+    /// queries that saturate with wider ones.
+    fn score_chunk(&self, database: &Sequence, queries: &[Sequence]) -> Vec<Result<AlignmentScore, Error>> {
+        let mut scores = vec![None; queries.len()];
+
+        self.rescore::<i8>(database, queries, &mut scores);
+        self.rescore::<i16>(database, queries, &mut scores);
+        self.rescore::<i32>(database, queries, &mut scores);
+
+        scores.into_iter().map(|score| score.ok_or(Error::ScoreOverflow)).collect()
+    }
+
+    /// Scores the queries that have no score yet with the given score type.
+    fn rescore<S: SimdScore>(&self, database: &Sequence, queries: &[Sequence], scores: &mut [Option<AlignmentScore>]) {
+        let missing: Vec<_> = (0..queries.len()).filter(|&q| scores[q].is_none()).collect();
+        if missing.is_empty() {
+            return;
+        }
+
+        let pending: Vec<_> = missing.iter().map(|&q| &queries[q]).collect();
+        for (q, score) in missing.into_iter().zip(self.score_with::<S>(database, &pending)) {
+            scores[q] = score;
+        }
+    }
+
+    /// Computes the scores of the given queries' alignments against the database
+    /// with the selected instruction set and the given score type. Queries whose
+    /// values saturate get no score.
+    fn score_with<S: SimdScore>(&self, database: &Sequence, queries: &[&Sequence]) -> Vec<Option<AlignmentScore>> {
+        // See the striped engine for why we need a function per instruction set.
+        unsafe {
+            match self.instruction_set {
+                InstructionSet::Scalar => inter_sequence::<S, Scalar<S>>(&self.scoring, self.mode, database, queries),
+                #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+                InstructionSet::Sse2 => inter_sequence_sse2::<S>(&self.scoring, self.mode, database, queries),
+                #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+                InstructionSet::Avx2 => inter_sequence_avx2::<S>(&self.scoring, self.mode, database, queries),
+                #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+impl Engine for InterSequenceEngine {
+    fn name(&self) -> String {
+        format!("Inter-Sequence (CPU, {})", self.instruction_set)
+    }
+
+    fn align<'a>(&self, database: &'a Sequence, query: &'a Sequence, metrics: &Arc<Mutex<Metrics>>) -> Result<AlignedPair<'a>, Error> {
+        // A single pair cannot be vectorized across sequences,
+        // thus we use the striped engine for the entire alignment.
+        self.striped.align(database, query, metrics)
+    }
+
+    fn align_batch<'a>(&self, database: &'a Sequence, queries: &'a [Sequence], metrics: &Arc<Mutex<Metrics>>) -> Vec<Result<AlignedPair<'a>, Error>> {
+        // Every query is a hit, thus the traceback is performed for all of them
+        self.search(database, queries, Some(i32::MIN), metrics).into_iter()
+            .map(|result| result.map(|result| result.alignment.unwrap()))
+            .collect()
+    }
+
+    fn score(&self, database: &Sequence, query: &Sequence, metrics: &Arc<Mutex<Metrics>>) -> Result<AlignmentScore, Error> {
+        let score = self.score_chunk(database, std::slice::from_ref(query)).pop().unwrap()?;
+
+        metrics.lock().unwrap().record_cell_updates(4 * (database.len() + 1) * (query.len() + 1));
+        metrics.lock().unwrap().record_sequence_pair();
+
+        Ok(score)
+    }
+
+    fn score_batch(&self, database: &Sequence, queries: &[Sequence], metrics: &Arc<Mutex<Metrics>>) -> Vec<Result<AlignmentScore, Error>> {
+        self.search(database, queries, None, metrics).into_iter()
+            .map(|result| result.map(|result| result.score))
+            .collect()
+    }
+}
+
+/// The state of a lane, i.e. of the query it currently aligns.
+struct Lane<S> {
+    /// The index of the query.
+    query: usize,
+    /// The column of the matrix (i.e. the position in the query).
+    j: usize,
+    /// The maximum so far (along with its cell) for local alignments.
+    best: (S, usize, usize),
+    /// The last row so far for (semi-)global alignments.
+    last_row: Vec<S>,
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse2")]
+unsafe fn inter_sequence_sse2<S: SimdScore>(scoring: &ScoringScheme, mode: AlignmentMode, database: &Sequence, queries: &[&Sequence]) -> Vec<Option<AlignmentScore>> {
+    inter_sequence::<S, S::Sse2>(scoring, mode, database, queries)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn inter_sequence_avx2<S: SimdScore>(scoring: &ScoringScheme, mode: AlignmentMode, database: &Sequence, queries: &[&Sequence]) -> Vec<Option<AlignmentScore>> {
+    inter_sequence::<S, S::Avx2>(scoring, mode, database, queries)
+}
+
+/// Computes the scores of the queries' alignments against the database
+/// and where they end, column by column with one query per lane. Each
+/// column of the matrix (i.e. each residue of the query) is computed
+/// from top to bottom, thus we only need to keep the last column of
+/// h and e (per lane) and the current value of f.
+///
+/// Since all arithmetic saturates, a query gets no score as soon as
+/// any of its values of h saturates (see `Score::is_saturated`). Its
+/// lane is then refilled right away.
+///
+/// The caller has to make sure that the instruction set is available.
+#[inline(always)]
+unsafe fn inter_sequence<S: Score, V: Vector<Element = S>>(scoring: &ScoringScheme, mode: AlignmentMode, database: &Sequence, queries: &[&Sequence]) -> Vec<Option<AlignmentScore>> {
+    let n = database.len();
+    let lanes = V::LANES;
+
+    // Without any residues in the database or the query,
+    // there is nothing to vectorize (only the borders).
+    // If even these overflow, the query gets no score.
+
+    let trivial = |query: &Sequence| n == 0 || query.residues.is_empty();
+    let mut results: Vec<_> = queries.iter()
+        .map(|query| if trivial(query) { score_rows(scoring, mode, database, query).ok() } else { None })
+        .collect();
+    let pending: Vec<_> = (0..queries.len()).filter(|&q| !trivial(queries[q])).collect();
+    let mut pending = pending.into_iter();
+
+    let gap_open = V::splat(S::saturating_from(scoring.gap_open as i32));
+    let gap_extend = V::splat(S::saturating_from(scoring.gap_extend as i32));
+    let min_score = V::splat(mode.min_score());
+
+    // The leftmost column is the same for all queries, whereas
+    // the topmost row depends on the position in each query.
+
+    let border_column: Vec<S> = (0..=n).map(|i| mode.border(scoring, i, 0).0).collect();
+
+    if !S::fits(scoring) || border_column.iter().any(|x| x.is_saturated()) {
+        return results;
+    }
+
+    // Create the last column of h and e for the rows 1 to n, with the
+    // lanes of each row stored contiguously. Additionally, create the
+    // per-lane inputs for the current column: the weights of each
+    // residue (of the alphabet) against the lanes' query residues and
+    // the topmost row (above and above left of the first row).
+
+    let alphabet_size = scoring.matrix.size();
+    let mut h = vec![S::ZERO; n * lanes];
+    let mut e = vec![S::ZERO; n * lanes];
+    let mut profile = vec![S::ZERO; alphabet_size * lanes];
+    let mut h_border = vec![S::ZERO; lanes];
+    let mut h_border_left = vec![S::ZERO; lanes];
+    let mut column_maxima = vec![S::ZERO; lanes];
+    let mut column_minima = vec![S::ZERO; lanes];
+    let mut states: Vec<Option<Lane<S>>> = (0..lanes).map(|_| None).collect();
+
+    loop {
+        // Refill the lanes whose query is done, starting at the leftmost column
+
+        for (k, state) in states.iter_mut().enumerate() {
+            if state.is_none() {
+                if let Some(query) = pending.next() {
+                    for i in 1..=n {
+                        h[(i - 1) * lanes + k] = border_column[i];
+                        e[(i - 1) * lanes + k] = S::NEGATIVE_INFINITY;
+                    }
+                    *state = Some(Lane { query, j: 0, best: (S::ZERO, n, 0), last_row: vec![border_column[n]] });
+                }
+            }
+        }
+
+        if states.iter().all(|state| state.is_none()) {
+            break;
+        }
+
+        // Move every lane to its next column (idle lanes compute garbage)
+
+        for (k, state) in states.iter_mut().enumerate() {
+            let residue = match state {
+                Some(lane) => {
+                    lane.j += 1;
+                    h_border[k] = mode.border(scoring, 0, lane.j).0;
+                    h_border_left[k] = mode.border(scoring, 0, lane.j - 1).0;
+                    queries[lane.query][lane.j - 1]
+                },
+                None => 0,
+            };
+            for r in 0..alphabet_size {
+                profile[r * lanes + k] = S::saturating_from(scoring.weight(r as u8, residue) as i32);
+            }
+        }
+
+        // Perform scoring stage for the column (dynamic programming-style)
+
+        let h_ptr = h.as_mut_ptr();
+        let e_ptr = e.as_mut_ptr();
+        let mut h_above = V::load(h_border.as_ptr());
+        let mut h_above_left = V::load(h_border_left.as_ptr());
+        let mut f_here = V::splat(S::NEGATIVE_INFINITY);
+        let mut column_maximum = V::splat(S::MIN);
+        let mut column_minimum = V::splat(S::MAX);
+
+        for i in 1..=n {
+            let offset = (i - 1) * lanes;
+            let h_left = V::load(h_ptr.add(offset));
+            let e_here = V::load(e_ptr.add(offset)).subs(gap_extend).max(h_left.subs(gap_open));
+            f_here = f_here.subs(gap_extend).max(h_above.subs(gap_open));
+            let h_here = h_above_left
+                .adds(V::load(profile.as_ptr().add(database[i - 1] as usize * lanes)))
+                .max(e_here)
+                .max(f_here)
+                .max(min_score);
+
+            h_here.store(h_ptr.add(offset));
+            e_here.store(e_ptr.add(offset));
+            column_maximum = column_maximum.max(h_here);
+            column_minimum = column_minimum.min(h_here);
+
+            h_above = h_here;
+            h_above_left = h_left;
+        }
+
+        column_maximum.store(column_maxima.as_mut_ptr());
+        column_minimum.store(column_minima.as_mut_ptr());
+
+        // Track the end of each lane's alignment, finishing the queries that are done
+        // and abandoning those that saturated (which thus get no score)
+
+        for (k, state) in states.iter_mut().enumerate() {
+            let Some(lane) = state else { continue };
+            let m = queries[lane.query].len();
+
+            if [column_maxima[k], column_minima[k], h_border[k]].iter().any(|x| x.is_saturated()) {
+                *state = None;
+                continue;
+            }
+
+            match mode.free_end_gaps() {
+                Some(_) => lane.last_row.push(h[(n - 1) * lanes + k]),
+                None => {
+                    // Since the columns are visited from left to right, the last
+                    // maximum (in row-major order) is the one in the lowest row.
+                    // Ties with an earlier column only win in the same or a lower row.
+                    let maximum = column_maxima[k];
+                    if maximum >= lane.best.0 {
+                        let i = (1..=n).rev().find(|&i| h[(i - 1) * lanes + k] == maximum).unwrap();
+                        if maximum > lane.best.0 || i >= lane.best.1 {
+                            lane.best = (maximum, i, lane.j);
+                        }
+                    }
+                },
+            }
+
+            if lane.j == m {
+                let score = match mode.free_end_gaps() {
+                    Some(gaps) => {
+                        let last_column: Vec<_> = (0..=n)
+                            .map(|i| if i == 0 { mode.border(scoring, 0, m).0 } else { h[(i - 1) * lanes + k] })
+                            .collect();
+                        let score = |i, j| if j == m { last_column[i] } else { lane.last_row[j] };
+                        let (i, j) = gaps.end(n, m, score);
+                        AlignmentScore { score: score(i, j).into(), database_end: i, query_end: j }
+                    },
+                    None => {
+                        let (score, i, j) = lane.best;
+                        AlignmentScore { score: score.into(), database_end: i, query_end: j }
+                    },
+                };
+                results[lane.query] = Some(score);
+                *state = None;
+            }
+        }
+    }
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::{InstructionSet, testing::assert_matches_naive};
+
+    use super::InterSequenceEngine;
+
+    #[test]
+    fn matches_naive() {
+        for name in InstructionSet::NAMES {
+            let instruction_set: InstructionSet = name.parse().unwrap();
+            if instruction_set.is_available() {
+                assert_matches_naive(|scoring, mode| InterSequenceEngine::new(scoring, mode, instruction_set).unwrap());
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+/// The diagonal-major layout of a matrix with the given dimensions,
+/// which stores the anti-diagonals (k = i + j) one after another and
+/// each anti-diagonal from the bottom left to the top right. Since the
+/// diagonals grow by one cell until they reach the shorter side a, stay
+/// at size a + 1 until the longer side b and then shrink by one cell
+/// again, the mapping between (i, j) and the index has a closed form
+/// in both directions.
+#[derive(Clone, Copy)]
+pub struct DiagonalMajorLayout {
+    height: usize,
+    width: usize,
+}
+
+impl DiagonalMajorLayout {
+    pub fn new(height: usize, width: usize) -> Self {
+        Self { height, width }
+    }
+
+    /// The length of the shorter side (minus the border).
+    fn a(self) -> usize {
+        self.height.min(self.width) - 1
+    }
+
+    /// The length of the longer side (minus the border).
+    fn b(self) -> usize {
+        self.height.max(self.width) - 1
+    }
+
+    /// The number of cells in the matrix.
+    pub fn size(self) -> usize {
+        self.height * self.width
+    }
+
+    /// The index of the first cell of diagonal k, i.e.
+    /// the number of cells in all diagonals before it.
+    pub fn offset(self, k: usize) -> usize {
+        let (a, b) = (self.a(), self.b());
+        if k <= a {
+            k * (k + 1) / 2
+        } else if k <= b {
+            a * (a + 1) / 2 + (k - a) * (a + 1)
+        } else {
+            let t = self.height + self.width - 1 - k;
+            self.size() - t * (t + 1) / 2
+        }
+    }
+
+    /// The column of the first cell of diagonal k.
+    fn lower(self, k: usize) -> usize {
+        (k + 1).saturating_sub(self.height)
+    }
+
+    /// The index of the cell (i, j).
+    pub fn index(self, i: usize, j: usize) -> usize {
+        self.diagonal_base(i + j) + j
+    }
+
+    /// The index of the cell (k - j, j) on diagonal k minus its column j.
+    /// Since the cells of a diagonal are contiguous, the index of any
+    /// of its cells is this base plus the cell's column.
+    pub fn diagonal_base(self, k: usize) -> usize {
+        self.offset(k) - self.lower(k)
+    }
+
+    /// The cell (i, j) at the given index.
+    pub fn cell(self, index: usize) -> (usize, usize) {
+        let (a, b) = (self.a(), self.b());
+        let growing = a * (a + 1) / 2;
+        let shrinking = self.offset(b + 1);
+
+        // Find the diagonal by inverting the offset in the corresponding part
+        let k = if index < growing {
+            // The largest k with k * (k + 1) / 2 <= index
+            ((8 * index + 1).isqrt() - 1) / 2
+        } else if index < shrinking {
+            a + (index - growing) / (a + 1)
+        } else {
+            // The smallest t with t * (t + 1) / 2 >= the number of remaining cells
+            let remaining = self.size() - index;
+            let t = ((8 * remaining + 1).isqrt() - 1) / 2;
+            let t = if t * (t + 1) / 2 < remaining { t + 1 } else { t };
+            self.height + self.width - 1 - t
+        };
+
+        let j = index - self.offset(k) + self.lower(k);
+        (k - j, j)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DiagonalMajorLayout;
+
+    #[test]
+    fn index_and_cell_round_trip() {
+        // Square, tall and wide matrices (including a single row or column)
+        for (height, width) in [(1, 1), (5, 5), (8, 3), (3, 8), (1, 6), (6, 1)] {
+            let layout = DiagonalMajorLayout::new(height, width);
+            let mut indices = Vec::new();
+
+            for i in 0..height {
+                for j in 0..width {
+                    let index = layout.index(i, j);
+                    assert_eq!(layout.cell(index), (i, j), "{}x{}", height, width);
+                    indices.push(index);
+                }
+            }
+
+            // Every index is used exactly once
+            indices.sort_unstable();
+            assert_eq!(indices, (0..layout.size()).collect::<Vec<_>>(), "{}x{}", height, width);
+        }
+    }
+
+    #[test]
+    fn diagonals_are_contiguous() {
+        let layout = DiagonalMajorLayout::new(4, 6);
+        for k in 0..(4 + 6 - 1) {
+            let (i, j) = layout.cell(layout.offset(k));
+            assert_eq!(i + j, k);
+            assert!(i == 3 || j == 0, "diagonal {} starts at ({}, {})", k, i, j);
+        }
+    }
+}
@@ -1,26 +1,75 @@
-mod constants;
+mod mode;
+mod score;
+mod scoring;
+mod substitution_matrix;
 mod naive;
 mod diagonal;
+mod opencl_device;
 mod opencl_diagonal;
+mod opencl_inter_task;
 mod optimized_diagonal;
-mod optimized_opencl_diagonal;
+mod layout;
+mod hirschberg;
+mod simd;
+mod striped;
+mod inter_sequence;
+mod tiled;
+mod traceback;
+mod workspace;
+#[cfg(test)]
+mod testing;
 
-pub use constants::*;
+pub use mode::*;
+pub use score::*;
+pub use scoring::*;
+pub use substitution_matrix::*;
 pub use naive::*;
 pub use diagonal::*;
+pub use opencl_device::*;
 pub use opencl_diagonal::*;
+pub use opencl_inter_task::*;
 pub use optimized_diagonal::*;
-pub use optimized_opencl_diagonal::*;
+pub use layout::*;
+pub use hirschberg::*;
+pub use simd::*;
+pub use striped::*;
+pub use inter_sequence::*;
+pub use tiled::*;
+pub use traceback::*;
+pub use workspace::*;
 
 use std::sync::{Arc, Mutex};
+use rayon::prelude::*;
 
-use crate::{model::{Sequence, AlignedPair}, metrics::Metrics};
+use crate::{model::{Sequence, AlignedPair, AlignmentScore}, metrics::Metrics, error::Error};
 
-/// A facility that computes the alignment of two sequences.
-pub trait Engine {
+/// A facility that computes the alignment of two sequences. Aligning
+/// a pair may fail (e.g. if its matrices do not fit into memory), in
+/// which case the engine can still be used for other pairs.
+pub trait Engine: Sync {
     /// The engine's name.
     fn name(&self) -> String;
 
     /// Aligns the given two sequences.
-    fn align<'a>(&self, database: &'a Sequence, query: &'a Sequence, metrics: &Arc<Mutex<Metrics>>) -> AlignedPair<'a>;
+    fn align<'a>(&self, database: &'a Sequence, query: &'a Sequence, metrics: &Arc<Mutex<Metrics>>) -> Result<AlignedPair<'a>, Error>;
+
+    /// Aligns each of the given queries to the database, returning the
+    /// alignments (or why they failed) in the order of the queries. By
+    /// default, this aligns the pairs in parallel, but engines may override
+    /// it to share work across the pairs (e.g. transfers to the GPU).
+    fn align_batch<'a>(&self, database: &'a Sequence, queries: &'a [Sequence], metrics: &Arc<Mutex<Metrics>>) -> Vec<Result<AlignedPair<'a>, Error>> {
+        queries.par_iter().map(|query| self.align(database, query, metrics)).collect()
+    }
+
+    /// Computes only the score of the given two sequences' alignment
+    /// and where it ends. Since this skips the traceback, it only
+    /// needs linear memory.
+    fn score(&self, database: &Sequence, query: &Sequence, metrics: &Arc<Mutex<Metrics>>) -> Result<AlignmentScore, Error>;
+
+    /// Computes only the scores of each of the given queries' alignments
+    /// to the database, returning them in the order of the queries. Like
+    /// `align_batch`, this scores the pairs in parallel by default.
+    fn score_batch(&self, database: &Sequence, queries: &[Sequence], metrics: &Arc<Mutex<Metrics>>) -> Vec<Result<AlignmentScore, Error>> {
+        queries.par_iter().map(|query| self.score(database, query, metrics)).collect()
+    }
 }
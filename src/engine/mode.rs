@@ -0,0 +1,162 @@
+use std::{fmt, str::FromStr};
+
+use crate::model::AlignmentScore;
+
+use super::{Score, ScoringScheme};
+
+/// The kind of alignment to compute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlignmentMode {
+    /// A local alignment (Smith-Waterman) of the best-matching
+    /// subsequences of the database and the query.
+    Local,
+    /// A global alignment (Needleman-Wunsch) of the entire
+    /// database and the entire query.
+    Global,
+    /// A global alignment in which some of the end gaps are free
+    /// (e.g. to fit the query into the database or to find the
+    /// overlap between a suffix and a prefix).
+    SemiGlobal(FreeEndGaps),
+}
+
+/// The end gaps that are not penalized in a semi-global alignment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FreeEndGaps {
+    /// Whether leading gaps in the database (i.e. skipping the start of the query) are free.
+    pub database_leading: bool,
+    /// Whether trailing gaps in the database (i.e. skipping the end of the query) are free.
+    pub database_trailing: bool,
+    /// Whether leading gaps in the query (i.e. skipping the start of the database) are free.
+    pub query_leading: bool,
+    /// Whether trailing gaps in the query (i.e. skipping the end of the database) are free.
+    pub query_trailing: bool,
+}
+
+impl AlignmentMode {
+    /// The names of the modes accepted by `FromStr`.
+    pub const NAMES: [&'static str; 4] = ["local", "global", "semi-global", "overlap"];
+
+    /// The end gaps that are free, or none for local alignments.
+    pub fn free_end_gaps(self) -> Option<FreeEndGaps> {
+        match self {
+            AlignmentMode::Local => None,
+            AlignmentMode::Global => Some(FreeEndGaps::default()),
+            AlignmentMode::SemiGlobal(gaps) => Some(gaps),
+        }
+    }
+
+    /// The lower bound for the values of h. Local alignments may start
+    /// anywhere, thus their values never drop below 0.
+    pub fn min_score<S: Score>(self) -> S {
+        match self {
+            AlignmentMode::Local => S::ZERO,
+            _ => S::NEGATIVE_INFINITY,
+        }
+    }
+
+    /// The values of h, e and f in the border cell (i, j) (i.e.
+    /// where i or j is 0). In (semi-)global alignments, the border
+    /// represents a leading gap in the database or the query (which
+    /// saturates if it does not fit into the score type).
+    pub fn border<S: Score>(self, scoring: &ScoringScheme, i: usize, j: usize) -> (S, S, S) {
+        match self.free_end_gaps() {
+            Some(gaps) if i == 0 && j > 0 => {
+                let h = if gaps.database_leading { S::ZERO } else { S::saturating_from(scoring.gap(j)) };
+                (h, h, S::NEGATIVE_INFINITY)
+            },
+            Some(gaps) if j == 0 && i > 0 => {
+                let h = if gaps.query_leading { S::ZERO } else { S::saturating_from(scoring.gap(i)) };
+                (h, S::NEGATIVE_INFINITY, h)
+            },
+            _ => (S::ZERO, S::NEGATIVE_INFINITY, S::NEGATIVE_INFINITY),
+        }
+    }
+
+    /// Finds the end of the alignment without the entire matrix h. For
+    /// local alignments, this only needs the (last) maximum in each row
+    /// along with its column, for (semi-)global alignments the last row
+    /// and the last column (including the border). Ties are resolved
+    /// towards the last cell (in row-major order), like in the engines.
+    pub fn find_end<S: Score>(self, row_maxima: &[(S, usize)], last_row: &[S], last_column: &[S]) -> AlignmentScore {
+        let n = last_column.len() - 1;
+        let m = last_row.len() - 1;
+        let (database_end, query_end, score) = match self.free_end_gaps() {
+            Some(gaps) => {
+                let score = |i, j| if j == m { last_column[i] } else { last_row[j] };
+                let (i, j) = gaps.end(n, m, score);
+                (i, j, score(i, j))
+            },
+            None => {
+                let (i, &(score, j)) = row_maxima.iter()
+                    .enumerate()
+                    .max_by_key(|&(_, &(score, _))| score)
+                    .unwrap();
+                (i, j, score)
+            },
+        };
+        AlignmentScore { score: score.into(), database_end, query_end }
+    }
+}
+
+impl FreeEndGaps {
+    /// Free gaps at both ends of the query, i.e. the query is fit
+    /// into the database (also known as glocal alignment).
+    pub const QUERY: FreeEndGaps = FreeEndGaps { database_leading: false, database_trailing: false, query_leading: true, query_trailing: true };
+    /// Free gaps at all ends, i.e. the two sequences overlap.
+    pub const ALL: FreeEndGaps = FreeEndGaps { database_leading: true, database_trailing: true, query_leading: true, query_trailing: true };
+
+    /// Finds the cell (i, j) that the traceback starts at, given the length n of the
+    /// database and m of the query. With free trailing gaps, the alignment may end
+    /// anywhere in the last column (query) or the last row (database).
+    pub fn end<S: Ord>(self, n: usize, m: usize, score: impl Fn(usize, usize) -> S) -> (usize, usize) {
+        let last_column = (0..=n).filter(|_| self.query_trailing).map(|i| (i, m));
+        let last_row = (0..=m).filter(|_| self.database_trailing).map(|j| (n, j));
+        last_column.chain(last_row)
+            .chain([(n, m)])
+            .max_by_key(|&(i, j)| score(i, j))
+            .unwrap()
+    }
+}
+
+impl FromStr for AlignmentMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "local" => Ok(AlignmentMode::Local),
+            "global" => Ok(AlignmentMode::Global),
+            "semi-global" => Ok(AlignmentMode::SemiGlobal(FreeEndGaps::QUERY)),
+            "overlap" => Ok(AlignmentMode::SemiGlobal(FreeEndGaps::ALL)),
+            _ => Err(format!(
+                "Unknown alignment mode '{}' (supported are: {})",
+                s, AlignmentMode::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for AlignmentMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlignmentMode::Local => write!(f, "local"),
+            AlignmentMode::Global => write!(f, "global"),
+            AlignmentMode::SemiGlobal(gaps) => write!(f, "semi-global ({})", gaps),
+        }
+    }
+}
+
+impl fmt::Display for FreeEndGaps {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<_> = [
+            (self.database_leading, "database leading"),
+            (self.database_trailing, "database trailing"),
+            (self.query_leading, "query leading"),
+            (self.query_trailing, "query trailing"),
+        ].into_iter().filter(|&(free, _)| free).map(|(_, name)| name).collect();
+        if names.is_empty() {
+            write!(f, "no free end gaps")
+        } else {
+            write!(f, "free {} gaps", names.join(", "))
+        }
+    }
+}
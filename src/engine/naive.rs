@@ -1,44 +1,63 @@
 use std::sync::{Arc, Mutex};
 
-use crate::{model::{Sequence, AlignedPair, AlignedSequence}, metrics::Metrics};
+use crate::{model::{Sequence, AlignedPair, AlignmentScore}, metrics::Metrics, error::Error, utils::Pool};
 
-use super::{Engine, G_INIT, G_EXT, WEIGHT_IF_EQ};
+use super::{AlignmentMode, Direction, Engine, Origin, Score, ScoringScheme, TracebackCell, Workspace, matrix_size, resized, traceback};
 
 /// An engine that computes alignments using the
 /// Smith-Waterman-Algorithm (naively) on the CPU.
-pub struct NaiveEngine;
+pub struct NaiveEngine {
+    scoring: ScoringScheme,
+    mode: AlignmentMode,
+    workspaces: Pool,
+}
 
 impl NaiveEngine {
-    fn weight(d: u8, q: u8) -> i16 {
-        if d == q { WEIGHT_IF_EQ } else { -WEIGHT_IF_EQ }
+    pub fn new(scoring: ScoringScheme, mode: AlignmentMode) -> Self {
+        Self { scoring, mode, workspaces: Pool::new() }
     }
-}
 
-impl Engine for NaiveEngine {
-    fn name(&self) -> String {
-        "Naive (CPU)".to_owned()
-    }
+    /// Aligns the given two sequences using the given score type,
+    /// or returns none if any value of h saturates.
+    fn align_with<'a, S: Score>(&self, database: &'a Sequence, query: &'a Sequence) -> Result<Option<AlignedPair<'a>>, Error> {
+        if !S::fits(&self.scoring) {
+            return Ok(None);
+        }
 
-    fn align<'a>(&self, database: &'a Sequence, query: &'a Sequence, metrics: &Arc<Mutex<Metrics>>) -> AlignedPair<'a> {
         let n = database.len();
         let m = query.len();
         let height = n + 1;
         let width = m + 1;
-        let size = height * width;
+        let size = matrix_size(n, m)?;
 
-        // Create scoring matrix h, helper matrix f and a
-        // helper matrix p that tracks the previous index
+        // Create scoring matrix h, helper matrix f and a helper
+        // matrix p that tracks the direction the value came from
+        // and whether e and f extend a gap (rather than opening one)
 
-        let mut h = vec![0; size];
-        let mut f = vec![0; size];
-        let mut p = vec![0; size];
+        let mut workspace = self.workspaces.take(Workspace::<S>::default);
+        let workspace = &mut *workspace;
+        let h = resized(&mut workspace.h, size)?;
+        let f = resized(&mut workspace.f, size)?;
+        let p = resized(&mut workspace.p, size)?;
+
+        // Initialize the borders (the leftmost column and the topmost row)
+
+        for i in 0..height {
+            (h[i * width], _, f[i * width]) = self.mode.border(&self.scoring, i, 0);
+        }
+        for j in 1..width {
+            (h[j], _, f[j]) = self.mode.border(&self.scoring, 0, j);
+        }
 
         // Perform scoring stage (dynamic programming-style)
 
+        let gap_open = S::saturating_from(self.scoring.gap_open as i32);
+        let gap_extend = S::saturating_from(self.scoring.gap_extend as i32);
+
         for i in 1..=n {
             // We don't need to store e as a matrix since we iterate
             // from left to right (thus we only need the last value)
-            let mut e_here: i16 = 0;
+            let (_, mut e_here, _): (S, S, S) = self.mode.border(&self.scoring, i, 0);
 
             for j in 1..=m {
                 // Compute indices for the neighboring cells
@@ -48,45 +67,159 @@ impl Engine for NaiveEngine {
                 let above_left = (i - 1) * width + j - 1;
 
                 // Compute helper values
-                e_here = (e_here - G_EXT).max(h[left] - G_INIT);
-                f[here] = (f[above] - G_EXT).max(h[above] - G_INIT);
+                let e_extend = e_here.saturating_sub(gap_extend);
+                let e_open = h[left].saturating_sub(gap_open);
+                let f_extend = f[above].saturating_sub(gap_extend);
+                let f_open = h[above].saturating_sub(gap_open);
+
+                e_here = e_extend.max(e_open);
+                f[here] = f_extend.max(f_open);
 
-                // Compute value and the remember the index the maximum came from
+                // Compute value and remember the direction the maximum came from
                 // (we need this later for the traceback phase)
                 let (max_origin, max_value) = [
-                    (0,          0),
-                    (above_left, h[above_left] + Self::weight(database[i - 1], query[j - 1])),
-                    (left,       e_here),
-                    (above,      f[here]),
+                    (Origin::Start,    self.mode.min_score()),
+                    (Origin::Diagonal, h[above_left].saturating_add(S::saturating_from(self.scoring.weight(database[i - 1], query[j - 1]) as i32))),
+                    (Origin::Left,     e_here),
+                    (Origin::Above,    f[here]),
                 ].into_iter().max_by_key(|&(_, x)| x).unwrap();
                 
                 h[here] = max_value;
-                p[here] = max_origin;
+                p[here] = Direction::new(max_origin, e_extend > e_open, f_extend > f_open);
             }
         }
 
-        metrics.lock().unwrap().record_cell_updates(4 * size);
+        // The values (and thus the traceback) are only exact if none of them saturated
+
+        if h.iter().any(|x| x.is_saturated()) {
+            return Ok(None);
+        }
 
         // Perform traceback stage (using the previously computed scoring matrix h)
 
-        let mut i = (0..size).max_by_key(|&i| h[i]).unwrap();
-        let mut database_indices = Vec::new();
-        let mut query_indices = Vec::new();
+        let end = match self.mode.free_end_gaps() {
+            Some(gaps) => gaps.end(n, m, |i, j| h[i * width + j]),
+            None => {
+                let end = (0..size).max_by_key(|&i| h[i]).unwrap();
+                (end / width, end % width)
+            },
+        };
+        Ok(Some(traceback(database, query, self.mode, end, |i, j| i * width + j, |i| TracebackCell {
+            score: h[i].into(),
+            direction: p[i],
+        })))
+    }
+}
 
-        while i > 0 && h[i] > 0 {
-            database_indices.push((i / width) - 1);
-            query_indices.push((i % width) - 1);
-            i = p[i];
-        }
+impl Engine for NaiveEngine {
+    fn name(&self) -> String {
+        "Naive (CPU)".to_owned()
+    }
+
+    fn align<'a>(&self, database: &'a Sequence, query: &'a Sequence, metrics: &Arc<Mutex<Metrics>>) -> Result<AlignedPair<'a>, Error> {
+        // Start with 8-bit scores and widen them if the alignment saturates
+        let aligned = self.align_with::<i8>(database, query).transpose()
+            .or_else(|| self.align_with::<i16>(database, query).transpose())
+            .or_else(|| self.align_with::<i32>(database, query).transpose())
+            .unwrap_or(Err(Error::ScoreOverflow))?;
+
+        metrics.lock().unwrap().record_cell_updates(4 * (database.len() + 1) * (query.len() + 1));
+        metrics.lock().unwrap().record_sequence_pair();
+
+        Ok(aligned)
+    }
 
-        database_indices.reverse();
-        query_indices.reverse();
+    fn score(&self, database: &Sequence, query: &Sequence, metrics: &Arc<Mutex<Metrics>>) -> Result<AlignmentScore, Error> {
+        let score = score_rows(&self.scoring, self.mode, database, query)?;
 
+        metrics.lock().unwrap().record_cell_updates(4 * (database.len() + 1) * (query.len() + 1));
         metrics.lock().unwrap().record_sequence_pair();
 
-        AlignedPair::new(
-            AlignedSequence::new(database, database_indices),
-            AlignedSequence::new(query, query_indices),
-        )
+        Ok(score)
     }
 }
+
+/// Computes the score of the alignment and where it ends by iterating
+/// over the rows. Since we only keep a single row, this needs linear
+/// memory.
+pub fn score_rows(scoring: &ScoringScheme, mode: AlignmentMode, database: &Sequence, query: &Sequence) -> Result<AlignmentScore, Error> {
+    score_rows_with::<i8>(scoring, mode, database, query)
+        .or_else(|| score_rows_with::<i16>(scoring, mode, database, query))
+        .or_else(|| score_rows_with::<i32>(scoring, mode, database, query))
+        .ok_or(Error::ScoreOverflow)
+}
+
+/// Computes the score like `score_rows` using the given score
+/// type, or returns none if any value of h saturates.
+fn score_rows_with<S: Score>(scoring: &ScoringScheme, mode: AlignmentMode, database: &Sequence, query: &Sequence) -> Option<AlignmentScore> {
+    if !S::fits(scoring) {
+        return None;
+    }
+
+    let n = database.len();
+    let m = query.len();
+    let height = n + 1;
+    let width = m + 1;
+
+    // We only keep a single row of h and f, which we update in-place
+    // while iterating from left to right. To find the end of the
+    // alignment, we additionally track the maximum of each row and
+    // the last column (the last row is what remains in h).
+
+    let mut h = vec![S::ZERO; width];
+    let mut f = vec![S::ZERO; width];
+
+    for j in 0..width {
+        (h[j], _, f[j]) = mode.border(scoring, 0, j);
+    }
+
+    let mut row_maxima = vec![(S::ZERO, 0); height];
+    let mut last_column: Vec<S> = (0..height).map(|i| mode.border(scoring, i, 0).0).collect();
+    let mut saturated = h.iter().any(|x| x.is_saturated());
+
+    let gap_open = S::saturating_from(scoring.gap_open as i32);
+    let gap_extend = S::saturating_from(scoring.gap_extend as i32);
+
+    row_maxima[0] = (0..width).map(|j| (h[j], j)).max_by_key(|&(x, _)| x).unwrap();
+    last_column[0] = h[m];
+
+    // Perform scoring stage (dynamic programming-style)
+
+    for i in 1..=n {
+        let (h_border, mut e_here, _): (S, S, S) = mode.border(scoring, i, 0);
+
+        // Remember the value above left before we overwrite it
+        let mut h_above_left = h[0];
+        h[0] = h_border;
+        row_maxima[i] = (h_border, 0);
+        saturated |= h_border.is_saturated();
+
+        for j in 1..=m {
+            // Compute helper values (h and f still hold the values above)
+            e_here = e_here.saturating_sub(gap_extend).max(h[j - 1].saturating_sub(gap_open));
+            f[j] = f[j].saturating_sub(gap_extend).max(h[j].saturating_sub(gap_open));
+
+            // Compute value
+            let h_here = h_above_left.saturating_add(S::saturating_from(scoring.weight(database[i - 1], query[j - 1]) as i32))
+                .max(e_here)
+                .max(f[j])
+                .max(mode.min_score());
+
+            h_above_left = h[j];
+            h[j] = h_here;
+            saturated |= h_here.is_saturated();
+
+            if h_here >= row_maxima[i].0 {
+                row_maxima[i] = (h_here, j);
+            }
+        }
+
+        last_column[i] = h[m];
+    }
+
+    if saturated {
+        return None;
+    }
+
+    Some(mode.find_end(&row_maxima, &h, &last_column))
+}
@@ -0,0 +1,119 @@
+use std::{fmt, str::FromStr};
+use ocl::{Device, DeviceType, Platform, enums::{DeviceInfo, DeviceInfoResult}};
+
+use crate::error::Error;
+
+/// The type of OpenCL devices to choose from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpenCLDeviceType {
+    Gpu,
+    Cpu,
+    Accelerator,
+    /// Any type of device.
+    All,
+}
+
+/// The OpenCL device that the OpenCL engines run on, i.e. the device with the
+/// given index among the devices of the given type on the given platform.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpenCLDeviceSelector {
+    pub platform_index: usize,
+    pub device_type: OpenCLDeviceType,
+    pub device_index: usize,
+}
+
+/// A selected device along with its platform.
+#[derive(Clone, Copy, Debug)]
+pub struct OpenCLDevice {
+    pub platform: Platform,
+    pub device: Device,
+}
+
+impl OpenCLDeviceType {
+    /// The names of the device types accepted by `FromStr`.
+    pub const NAMES: [&'static str; 4] = ["gpu", "cpu", "accelerator", "all"];
+
+    /// The corresponding flags of OpenCL.
+    fn flags(self) -> DeviceType {
+        match self {
+            OpenCLDeviceType::Gpu => DeviceType::GPU,
+            OpenCLDeviceType::Cpu => DeviceType::CPU,
+            OpenCLDeviceType::Accelerator => DeviceType::ACCELERATOR,
+            OpenCLDeviceType::All => DeviceType::ALL,
+        }
+    }
+
+    /// The type of the given device.
+    pub fn of(device: Device) -> Self {
+        let flags = device.info(DeviceInfo::Type).ok().and_then(|info| match info {
+            DeviceInfoResult::Type(flags) => Some(flags),
+            _ => None,
+        });
+        match flags {
+            Some(flags) if flags.contains(DeviceType::GPU) => OpenCLDeviceType::Gpu,
+            Some(flags) if flags.contains(DeviceType::CPU) => OpenCLDeviceType::Cpu,
+            Some(flags) if flags.contains(DeviceType::ACCELERATOR) => OpenCLDeviceType::Accelerator,
+            _ => OpenCLDeviceType::All,
+        }
+    }
+}
+
+impl OpenCLDeviceSelector {
+    /// Finds the selected platform and device.
+    pub fn select(self) -> Result<OpenCLDevice, Error> {
+        let platforms = opencl_platforms();
+        let platform = *platforms.get(self.platform_index).ok_or(Error::PlatformNotFound {
+            index: self.platform_index,
+            available: platforms.len(),
+        })?;
+        let devices = Device::list(platform, Some(self.device_type.flags())).unwrap_or_default();
+        let device = *devices.get(self.device_index).ok_or(Error::DeviceNotFound {
+            platform_index: self.platform_index,
+            device_type: self.device_type,
+            index: self.device_index,
+            available: devices.len(),
+        })?;
+        Ok(OpenCLDevice { platform, device })
+    }
+}
+
+/// The available OpenCL platforms (none if there is no OpenCL runtime).
+pub fn opencl_platforms() -> Vec<Platform> {
+    ocl::core::get_platform_ids()
+        .map(|ids| ids.into_iter().map(Platform::new).collect())
+        .unwrap_or_default()
+}
+
+/// A short description of the given device for the names of the engines,
+/// e.g. `GPU: Apple M1 Pro`.
+pub fn opencl_device_label(device: Device) -> String {
+    format!("{}: {}", OpenCLDeviceType::of(device), device.name().unwrap_or_default())
+}
+
+impl FromStr for OpenCLDeviceType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "gpu" => Ok(OpenCLDeviceType::Gpu),
+            "cpu" => Ok(OpenCLDeviceType::Cpu),
+            "accelerator" => Ok(OpenCLDeviceType::Accelerator),
+            "all" => Ok(OpenCLDeviceType::All),
+            _ => Err(format!(
+                "Unknown device type '{}' (supported are: {})",
+                s, OpenCLDeviceType::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OpenCLDeviceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpenCLDeviceType::Gpu => write!(f, "GPU"),
+            OpenCLDeviceType::Cpu => write!(f, "CPU"),
+            OpenCLDeviceType::Accelerator => write!(f, "Accelerator"),
+            OpenCLDeviceType::All => write!(f, "Device"),
+        }
+    }
+}
@@ -1,140 +1,687 @@
-use std::sync::{Arc, Mutex};
-use ocl::{Buffer, core::{MEM_WRITE_ONLY, MEM_READ_ONLY}, Queue, Program, Context, Platform, Device, DeviceType, Kernel};
+use std::{mem, slice, sync::{Arc, Mutex, RwLock}};
+use rayon::prelude::*;
+use ocl::{Buffer, MemFlags, OclPrm, core::{MEM_WRITE_ONLY, MEM_READ_ONLY, MEM_READ_WRITE}, enums::{DeviceInfo, DeviceInfoResult}, Queue, Program, Context, Device, Kernel};
 
-use crate::{model::{Sequence, AlignedPair, AlignedSequence}, metrics::Metrics};
+use crate::{model::{Sequence, AlignedPair, AlignmentScore, Operation}, metrics::Metrics, error::Error, utils::{Pool, Pooled}};
 
-use super::{Engine, G_INIT, G_EXT, WEIGHT_IF_EQ};
+use super::{AlignmentMode, Direction, Engine, OpenCLDevice, Score, ScoringScheme, matrix_size, opencl_device_label};
 
 /// An engine that computes alignments using the
 /// Smith-Waterman-Algorithm with OpenCL on the
 /// GPU by parallelizing over the diagonals.
 pub struct OpenCLDiagonalEngine {
-    program: Program,
+    programs: ScorePrograms,
     device: Device,
     context: Context,
+    scoring: ScoringScheme,
+    mode: AlignmentMode,
+    gpu_matrix: Buffer<i16>,
+    resident: ResidentDatabase,
+    workspaces: Pool,
 }
 
 impl OpenCLDiagonalEngine {
-    pub fn new(gpu_index: usize) -> Self {
+    pub fn new(opencl: OpenCLDevice, scoring: ScoringScheme, mode: AlignmentMode) -> Result<Self, Error> {
+        Self::with_sources(opencl, scoring, mode, &[])
+    }
+
+    /// Creates the engine, additionally building the given sources into its
+    /// programs. This lets other engines (see `OpenCLInterTaskEngine`) run their
+    /// kernels with its context, substitution matrix and database on the GPU.
+    pub fn with_sources(opencl: OpenCLDevice, scoring: ScoringScheme, mode: AlignmentMode, sources: &[&str]) -> Result<Self, Error> {
         // The OpenCL program source code.
-        let program_src: String = include_str!("opencl_diagonal.cl")
-            .replace("G_EXT", &G_EXT.to_string())
-            .replace("G_INIT", &G_INIT.to_string())
-            .replace("WEIGHT_IF_EQ", &WEIGHT_IF_EQ.to_string());
-
-        // Fetch platform and device
-        let platform = Platform::default();
-        let device = Device::list(platform, Some(DeviceType::GPU))
-            .unwrap()
-            .into_iter()
-            .nth(gpu_index)
-            .expect("GPU not found for OpenCL");
+        let program_src = include_str!("opencl_diagonal.cl");
+
+        // Unpack the (selected) platform and device
+        let OpenCLDevice { platform, device } = opencl;
 
         // Create the context
         let context = Context::builder()
             .platform(platform)
             .devices(device)
-            .build()
-            .unwrap();
+            .build()?;
 
-        // Create the program
-        let program = Program::builder()
-            .src(program_src)
-            .build(&context)
-            .unwrap();
+        // Create the program (once per score type)
+        let mut program_srcs = vec![program_src, include_str!("opencl_wavefront.cl"), include_str!("opencl_traceback.cl"), include_str!("opencl_score.cl")];
+        program_srcs.extend_from_slice(sources);
+        let programs = ScorePrograms::build(&context, &program_srcs)?;
 
-        Self { program, device, context }
+        // Copy the substitution matrix to the GPU
+        let gpu_matrix: Buffer<i16> = Buffer::builder()
+            .context(&context)
+            .len(scoring.matrix.scores().len())
+            .flags(MEM_READ_ONLY)
+            .copy_host_slice(scoring.matrix.scores())
+            .build()?;
+
+        Ok(Self { programs, device, context, scoring, mode, gpu_matrix, resident: ResidentDatabase::default(), workspaces: Pool::new() })
     }
-}
 
-impl Engine for OpenCLDiagonalEngine {
-    fn name(&self) -> String {
-        format!("OpenCL Diagonal (GPU: {})", self.device.name().unwrap())
+    /// Copies the given database sequences to the GPU, where they stay for the
+    /// lifetime of the engine. Aligning (or scoring) queries against one of them
+    /// then only copies the query to the GPU.
+    pub fn register_database(&self, sequences: &[Sequence]) -> Result<(), Error> {
+        self.resident.register(&*self.queue()?, sequences)
+    }
+
+    /// The device that the engine runs on.
+    pub fn device(&self) -> Device {
+        self.device
+    }
+
+    /// The programs of the engine (one per score type).
+    pub fn programs(&self) -> &ScorePrograms {
+        &self.programs
+    }
+
+    /// The substitution matrix on the GPU.
+    pub fn gpu_matrix(&self) -> &Buffer<i16> {
+        &self.gpu_matrix
+    }
+
+    /// The registered database sequences on the GPU.
+    pub fn resident(&self) -> &ResidentDatabase {
+        &self.resident
+    }
+
+    /// Takes a queue from the engine's pool (or creates one).
+    pub fn queue(&self) -> Result<Pooled<'_, Queue>, Error> {
+        pooled_queue(&self.workspaces, &self.context, self.device)
+    }
+
+    /// Aligns the given queries to the database, each with the smallest
+    /// score type that does not saturate (see `realign_with`).
+    fn align_chunk<'a>(&self, database: &'a Sequence, queries: &'a [Sequence]) -> Vec<Result<AlignedPair<'a>, Error>> {
+        // Start with 8-bit scores and widen them for the alignments that saturate
+        let mut aligned: Vec<_> = queries.iter().map(|_| None).collect();
+        let setup = self.realign_with::<i8>(database, queries, &mut aligned)
+            .and_then(|()| self.realign_with::<i16>(database, queries, &mut aligned))
+            .and_then(|()| self.realign_with::<i32>(database, queries, &mut aligned));
+
+        // The alignments that are still missing either overflow even with 32-bit
+        // scores or could not be computed since setting up the GPU failed
+        aligned.into_iter().map(|a| match (a, &setup) {
+            (Some(aligned), _) => aligned,
+            (None, Ok(())) => Err(Error::ScoreOverflow),
+            (None, Err(e)) => Err(e.clone()),
+        }).collect()
+    }
+
+    /// Aligns the queries that have no alignment (or error) yet using the given
+    /// score type. Since they share a workspace, the database is only copied once
+    /// (or not at all, if it is registered). Fails if the workspace cannot be set up.
+    fn realign_with<'a, S: Score + OclPrm>(&self, database: &'a Sequence, queries: &'a [Sequence], aligned: &mut [Option<Result<AlignedPair<'a>, Error>>]) -> Result<(), Error> {
+        if !S::fits(&self.scoring) || aligned.iter().all(|a| a.is_some()) {
+            return Ok(());
+        }
+
+        // Take the buffers (on the CPU and the GPU) and a queue from the pool
+        // and copy the database to the GPU (unless it is registered already)
+        let mut workspace = self.workspaces.try_take(|| OpenCLWorkspace::<S>::new(&self.context, self.device))?;
+        let gpu_database = match self.resident.get(database) {
+            Some(buffer) => buffer,
+            None => {
+                workspace.upload_database(database)?;
+                workspace.database.clone()
+            },
+        };
+
+        for (query, aligned) in queries.iter().zip(aligned) {
+            if aligned.is_none() {
+                *aligned = self.align_with(&mut workspace, &gpu_database, database, query).transpose();
+            }
+        }
+
+        Ok(())
     }
 
-    fn align<'a>(&self, database: &'a Sequence, query: &'a Sequence, metrics: &Arc<Mutex<Metrics>>) -> AlignedPair<'a> {
+    /// Aligns the given two sequences using the given score type (and the given
+    /// workspace and copy of the database on the GPU), or returns none if any
+    /// value of h saturates.
+    fn align_with<'a, S: Score + OclPrm>(&self, workspace: &mut OpenCLWorkspace<S>, gpu_database: &Buffer<u8>, database: &'a Sequence, query: &'a Sequence) -> Result<Option<AlignedPair<'a>>, Error> {
         let n = database.len();
         let m = query.len();
         let height = n + 1;
         let width = m + 1;
-        let size = height * width;
-
-        // Create a queue
-        let queue = Queue::new(&self.context, self.device, None).unwrap();
-
-        // Allocate buffers on the GPU.
-        let gpu_database: Buffer<u8> = Buffer::builder().queue(queue.clone()).len(n).flags(MEM_READ_ONLY).build().unwrap();
-        let gpu_query: Buffer<u8> = Buffer::builder().queue(queue.clone()).len(m).flags(MEM_READ_ONLY).build().unwrap();
-        let gpu_h: Buffer<i16> = Buffer::builder().queue(queue.clone()).len(size).build().unwrap();
-        let gpu_e: Buffer<i16> = Buffer::builder().queue(queue.clone()).len(size).build().unwrap();
-        let gpu_f: Buffer<i16> = Buffer::builder().queue(queue.clone()).len(size).build().unwrap();
-        let gpu_p: Buffer<u32> = Buffer::builder().queue(queue.clone()).len(size).flags(MEM_WRITE_ONLY).build().unwrap();
-
-        // Copy database and query to GPU.
-        gpu_database.write(&database.raw).enq().unwrap();
-        gpu_query.write(&query.raw).enq().unwrap();
-
-        // Create the kernel.
-        let mut kernel = Kernel::builder()
-            .name("smith_waterman_diagonal")
-            .program(&self.program)
-            .queue(queue)
+
+        workspace.upload_query(n, query)?;
+        workspace.upload_borders(&self.scoring, self.mode, n, m)?;
+
+        // Create the kernel, whose work-group walks over all diagonals
+        // (see `opencl_wavefront.cl`), and enqueue it once.
+        let lanes = wavefront_size(self.device, m)?;
+        let kernel = Kernel::builder()
+            .name("smith_waterman_wavefront")
+            .program(self.programs.get::<S>())
+            .queue(workspace.queue.clone())
+            .global_work_size(lanes)
+            .local_work_size(lanes)
+            .arg(height as u32)
             .arg(width as u32)
-            .arg(&gpu_database)
-            .arg(&gpu_query)
-            .arg(&gpu_h)
-            .arg(&gpu_e)
-            .arg(&gpu_f)
-            .arg(&gpu_p)
-            .build()
-            .unwrap();
-
-        // We start at 2 since the first interesting (non-border)
-        // diagonal starts at i = 2 (going rightwards upwards).
-        for k in 2..=(n + m) {
-            // The lower and upper bounds for the diagonal('s j index)
-            // Derived from rearranging the equations
-            // `k - j = i < height` and `j < width` (our base range is `1..k`).
-            let lower = (k as isize - height as isize + 1).max(1) as usize;
-            let upper = k.min(width);
-
-            // Set the size and offset of the kernel (the indices represent k and j)
-            kernel.set_default_global_work_size((1, upper - lower).into());
-            kernel.set_default_global_work_offset((k, lower).into());
-
-            // Enqueue the kernel.
-            unsafe { kernel.enq().unwrap(); }
+            .arg(S::saturating_from(self.scoring.gap_open as i32))
+            .arg(S::saturating_from(self.scoring.gap_extend as i32))
+            .arg(self.mode.min_score::<S>())
+            .arg(self.scoring.matrix.size() as u32)
+            .arg(&self.gpu_matrix)
+            .arg(gpu_database)
+            .arg(&workspace.query)
+            .arg(&workspace.border_row)
+            .arg(&workspace.border_column)
+            .arg(&workspace.h)
+            .arg(&workspace.e)
+            .arg(&workspace.f)
+            .arg(&workspace.p)
+            .arg_local::<S>(2 * lanes)
+            .arg_local::<S>(2 * lanes)
+            .build()?;
+
+        unsafe { kernel.enq()?; }
+
+        // Find the end of the alignment and trace it back on the GPU
+        opencl_traceback(self.programs.get::<S>(), workspace, self.mode, gpu_database, database, query)
+    }
+}
+
+impl Engine for OpenCLDiagonalEngine {
+    fn name(&self) -> String {
+        format!("OpenCL Diagonal ({})", opencl_device_label(self.device))
+    }
+
+    fn align<'a>(&self, database: &'a Sequence, query: &'a Sequence, metrics: &Arc<Mutex<Metrics>>) -> Result<AlignedPair<'a>, Error> {
+        let aligned = self.align_chunk(database, slice::from_ref(query)).pop().unwrap()?;
+
+        metrics.lock().unwrap().record_cell_updates(4 * (database.len() + 1) * (query.len() + 1));
+        metrics.lock().unwrap().record_sequence_pair();
+
+        Ok(aligned)
+    }
+
+    fn align_batch<'a>(&self, database: &'a Sequence, queries: &'a [Sequence], metrics: &Arc<Mutex<Metrics>>) -> Vec<Result<AlignedPair<'a>, Error>> {
+        // Every task aligns a chunk of the queries, sharing the database on the GPU
+        queries.par_chunks(OPENCL_CHUNK_SIZE).flat_map_iter(|chunk| {
+            let aligned = self.align_chunk(database, chunk);
+
+            let mut metrics = metrics.lock().unwrap();
+            for query in chunk {
+                metrics.record_cell_updates(4 * (database.len() + 1) * (query.len() + 1));
+                metrics.record_sequence_pair();
+            }
+
+            aligned
+        }).collect()
+    }
+
+    fn score(&self, database: &Sequence, query: &Sequence, metrics: &Arc<Mutex<Metrics>>) -> Result<AlignmentScore, Error> {
+        let queue = self.queue()?;
+        let gpu_database = self.resident.upload(&queue, database)?;
+        let score = opencl_score_diagonals(&self.programs, queue.clone(), &self.scoring, &self.gpu_matrix, self.mode, &gpu_database, query)?;
+
+        metrics.lock().unwrap().record_cell_updates(4 * (database.len() + 1) * (query.len() + 1));
+        metrics.lock().unwrap().record_sequence_pair();
+
+        Ok(score)
+    }
+}
+
+/// Takes a queue for the given device from the given pool (or creates one),
+/// which the OpenCL engines share with their workspaces.
+pub fn pooled_queue<'a>(pool: &'a Pool, context: &Context, device: Device) -> Result<Pooled<'a, Queue>, Error> {
+    pool.try_take(|| Queue::new(context, device, None).map_err(Error::from))
+}
+
+/// The maximum number of work-items in the work-group that walks over the
+/// diagonals (see `opencl_wavefront.cl`), each of which computes a column.
+pub const OPENCL_WAVEFRONT_SIZE: usize = 256;
+
+/// The number of work-items for computing a matrix with the given number
+/// of (non-border) columns, which the device has to support in a work-group.
+pub fn wavefront_size(device: Device, m: usize) -> Result<usize, Error> {
+    Ok(m.min(OPENCL_WAVEFRONT_SIZE).min(device.max_wg_size()?).max(1))
+}
+
+/// The number of work-groups and the maximum number of work-items per group
+/// that find the maximum of h (see `opencl_traceback.cl`).
+pub const OPENCL_REDUCTION_GROUPS: usize = 64;
+pub const OPENCL_REDUCTION_SIZE: usize = 256;
+
+/// The operations in the order of their codes in `opencl_traceback.cl`.
+const OPENCL_OPERATIONS: [Operation; 4] = [Operation::Match, Operation::Mismatch, Operation::Insertion, Operation::Deletion];
+
+/// Finds the end of the alignment and traces it back on the GPU, given the
+/// workspace holding the computed matrices (and the database on the GPU). Thus
+/// only the maximum of every work-group and the path are copied back rather
+/// than the matrices. The program needs to include the kernels from
+/// `opencl_traceback.cl`. Returns none if any value of h saturates.
+pub fn opencl_traceback<'a, S: Score + OclPrm>(program: &Program, workspace: &mut OpenCLWorkspace<S>, mode: AlignmentMode, gpu_database: &Buffer<u8>, database: &'a Sequence, query: &'a Sequence) -> Result<Option<AlignedPair<'a>>, Error> {
+    let height = database.len() + 1;
+    let width = query.len() + 1;
+    let size = height * width;
+
+    // Find the maximum of h (and whether any value saturates) per work-group
+    let lanes = OPENCL_REDUCTION_SIZE.min(workspace.queue.device().max_wg_size()?);
+    let kernel = Kernel::builder()
+        .name("smith_waterman_maximum")
+        .program(program)
+        .queue(workspace.queue.clone())
+        .global_work_size(OPENCL_REDUCTION_GROUPS * lanes)
+        .local_work_size(lanes)
+        .arg(size as u32)
+        .arg(&workspace.h)
+        .arg(&workspace.maxima)
+        .arg(&workspace.maximum_indices)
+        .arg(&workspace.saturated)
+        .arg_local::<S>(lanes)
+        .arg_local::<u32>(lanes)
+        .arg_local::<i32>(lanes)
+        .build()?;
+
+    unsafe { kernel.enq()?; }
+
+    let mut maxima = vec![S::ZERO; OPENCL_REDUCTION_GROUPS];
+    let mut maximum_indices = vec![0u32; OPENCL_REDUCTION_GROUPS];
+    let mut saturated = vec![0i32; OPENCL_REDUCTION_GROUPS];
+
+    workspace.maxima.read(&mut maxima).enq()?;
+    workspace.maximum_indices.read(&mut maximum_indices).enq()?;
+    workspace.saturated.read(&mut saturated).enq()?;
+
+    // The values (and thus the traceback) are only exact if none of them saturated
+
+    if saturated.iter().any(|&s| s != 0) {
+        return Ok(None);
+    }
+
+    // Merge the maxima of the work-groups (preferring the last index, like the
+    // work-groups themselves) to find the end of local alignments. The end of
+    // (semi-)global alignments is found by the traceback kernel.
+
+    let (_, index) = maxima.into_iter().zip(maximum_indices).max().unwrap();
+    let end = (index as usize / width, index as usize % width);
+
+    // Perform traceback stage on the GPU (using the previously computed matrices h and p)
+
+    let gaps = mode.free_end_gaps();
+    let kernel = Kernel::builder()
+        .name("smith_waterman_traceback")
+        .program(program)
+        .queue(workspace.queue.clone())
+        .global_work_size(1)
+        .arg(height as u32)
+        .arg(width as u32)
+        .arg(end.0 as u32)
+        .arg(end.1 as u32)
+        .arg(gaps.is_none() as u32)
+        .arg(gaps.is_some_and(|gaps| gaps.database_leading) as u32)
+        .arg(gaps.is_some_and(|gaps| gaps.database_trailing) as u32)
+        .arg(gaps.is_some_and(|gaps| gaps.query_leading) as u32)
+        .arg(gaps.is_some_and(|gaps| gaps.query_trailing) as u32)
+        .arg(gpu_database)
+        .arg(&workspace.query)
+        .arg(&workspace.h)
+        .arg(&workspace.p)
+        .arg(&workspace.path)
+        .arg(&workspace.trace)
+        .build()?;
+
+    unsafe { kernel.enq()?; }
+
+    let mut trace = [0i32; 4];
+    workspace.trace.read(&mut trace[..]).enq()?;
+    let [score, database_start, query_start, length] = trace;
+
+    let mut path = vec![0u8; length as usize];
+    if length > 0 {
+        workspace.path.read(&mut path).enq()?;
+    }
+
+    // The path leads from the end to the start of the alignment
+    let operations = path.into_iter().rev().map(|code| OPENCL_OPERATIONS[code as usize]).collect();
+    Ok(Some(AlignedPair::new(database, query, database_start as usize, query_start as usize, operations, score)))
+}
+
+/// The number of queries each (parallel) task of a batch aligns with
+/// the same workspace (and thus without copying the database again).
+pub const OPENCL_CHUNK_SIZE: usize = 16;
+
+/// The buffers on the GPU for aligning two sequences with the given score
+/// type, along with a queue. These are kept in a pool (see `Pool`) and
+/// reused across alignments. Like the workspaces
+/// of the CPU engines (see `Workspace`), the buffers only ever grow.
+pub struct OpenCLWorkspace<S: OclPrm> {
+    pub queue: Queue,
+    pub database: Buffer<u8>,
+    pub query: Buffer<u8>,
+    pub border_row: Buffer<S>,
+    pub border_column: Buffer<S>,
+    pub h: Buffer<S>,
+    pub e: Buffer<S>,
+    pub f: Buffer<S>,
+    pub p: Buffer<Direction>,
+    pub maxima: Buffer<S>,
+    pub maximum_indices: Buffer<u32>,
+    pub saturated: Buffer<i32>,
+    pub path: Buffer<u8>,
+    pub trace: Buffer<i32>,
+}
+
+// SAFETY: A direction is a single byte (see its representation).
+unsafe impl OclPrm for Direction {}
+
+impl<S: Score + OclPrm> OpenCLWorkspace<S> {
+    pub fn new(context: &Context, device: Device) -> Result<Self, Error> {
+        let queue = Queue::new(context, device, None)?;
+        Ok(Self {
+            database: gpu_buffer(&queue, MEM_READ_ONLY, 1)?,
+            query: gpu_buffer(&queue, MEM_READ_ONLY, 1)?,
+            border_row: gpu_buffer(&queue, MEM_READ_ONLY, 1)?,
+            border_column: gpu_buffer(&queue, MEM_READ_ONLY, 1)?,
+            h: gpu_buffer(&queue, MEM_READ_WRITE, 1)?,
+            e: gpu_buffer(&queue, MEM_READ_WRITE, 1)?,
+            f: gpu_buffer(&queue, MEM_READ_WRITE, 1)?,
+            p: gpu_buffer(&queue, MEM_READ_WRITE, 1)?,
+            maxima: gpu_buffer(&queue, MEM_WRITE_ONLY, OPENCL_REDUCTION_GROUPS)?,
+            maximum_indices: gpu_buffer(&queue, MEM_WRITE_ONLY, OPENCL_REDUCTION_GROUPS)?,
+            saturated: gpu_buffer(&queue, MEM_WRITE_ONLY, OPENCL_REDUCTION_GROUPS)?,
+            path: gpu_buffer(&queue, MEM_WRITE_ONLY, 1)?,
+            trace: gpu_buffer(&queue, MEM_WRITE_ONLY, 4)?,
+            queue,
+        })
+    }
+
+    /// Copies the database to the GPU, where it stays for all
+    /// queries aligned with this workspace (until the next one).
+    pub fn upload_database(&mut self, database: &Sequence) -> Result<(), Error> {
+        reserve_gpu_buffer(&self.queue, &mut self.database, MEM_READ_ONLY, database.len())?;
+        write_gpu_buffer(&self.database, &database.residues)?;
+        Ok(())
+    }
+
+    /// Copies the query to the GPU and grows the buffers (if needed) to hold
+    /// the matrices and the path for a database of the given length. Fails if
+    /// the matrices exceed the largest buffer that the device can allocate
+    /// (or the 32-bit indices of the kernels).
+    pub fn upload_query(&mut self, n: usize, query: &Sequence) -> Result<(), Error> {
+        let m = query.len();
+        let size = matrix_size(n, m)?;
+        let max_bytes = match self.queue.device().info(DeviceInfo::MaxMemAllocSize)? {
+            DeviceInfoResult::MaxMemAllocSize(bytes) => bytes,
+            _ => u64::MAX,
+        };
+        if size > u32::MAX as usize || (size as u64).saturating_mul(mem::size_of::<S>() as u64) > max_bytes {
+            return Err(Error::MatrixTooLarge { database: n, query: m });
         }
 
-        metrics.lock().unwrap().record_cell_updates(4 * size);
+        reserve_gpu_buffer(&self.queue, &mut self.query, MEM_READ_ONLY, m)?;
+        write_gpu_buffer(&self.query, &query.residues)?;
+        reserve_gpu_buffer(&self.queue, &mut self.h, MEM_READ_WRITE, size)?;
+        reserve_gpu_buffer(&self.queue, &mut self.e, MEM_READ_WRITE, size)?;
+        reserve_gpu_buffer(&self.queue, &mut self.f, MEM_READ_WRITE, size)?;
+        reserve_gpu_buffer(&self.queue, &mut self.p, MEM_READ_WRITE, size)?;
+        reserve_gpu_buffer(&self.queue, &mut self.path, MEM_WRITE_ONLY, n + m)?;
+        Ok(())
+    }
+
+    /// Copies the borders of h (the topmost row and the leftmost column) for
+    /// aligning sequences of the given lengths to the GPU, where the kernel
+    /// from `opencl_wavefront.cl` fills them into the matrices.
+    pub fn upload_borders(&mut self, scoring: &ScoringScheme, mode: AlignmentMode, n: usize, m: usize) -> Result<(), Error> {
+        let border_row: Vec<S> = (0..=m).map(|j| mode.border(scoring, 0, j).0).collect();
+        let border_column: Vec<S> = (0..=n).map(|i| mode.border(scoring, i, 0).0).collect();
+
+        reserve_gpu_buffer(&self.queue, &mut self.border_row, MEM_READ_ONLY, m + 1)?;
+        reserve_gpu_buffer(&self.queue, &mut self.border_column, MEM_READ_ONLY, n + 1)?;
+        write_gpu_buffer(&self.border_row, &border_row)?;
+        write_gpu_buffer(&self.border_column, &border_column)?;
+        Ok(())
+    }
+}
+
+/// Allocates a buffer on the GPU (with at least one element,
+/// since OpenCL does not support empty buffers).
+pub fn gpu_buffer<T: OclPrm>(queue: &Queue, flags: MemFlags, len: usize) -> Result<Buffer<T>, Error> {
+    Ok(Buffer::builder().queue(queue.clone()).len(len.max(1)).flags(flags).build()?)
+}
+
+/// Allocates a buffer on the GPU holding a copy of the given data.
+pub fn gpu_buffer_from<T: OclPrm>(queue: &Queue, flags: MemFlags, data: &[T]) -> Result<Buffer<T>, Error> {
+    let buffer = gpu_buffer(queue, flags, data.len())?;
+    write_gpu_buffer(&buffer, data)?;
+    Ok(buffer)
+}
+
+/// Copies the given data to the start of the given buffer (unless it
+/// is empty, since OpenCL does not support empty writes either).
+pub fn write_gpu_buffer<T: OclPrm>(buffer: &Buffer<T>, data: &[T]) -> Result<(), Error> {
+    if !data.is_empty() {
+        buffer.write(data).enq()?;
+    }
+    Ok(())
+}
+
+/// Replaces the given buffer by a larger one if it is too small.
+fn reserve_gpu_buffer<T: OclPrm>(queue: &Queue, buffer: &mut Buffer<T>, flags: MemFlags, len: usize) -> Result<(), Error> {
+    if buffer.len() < len {
+        *buffer = gpu_buffer(queue, flags, len)?;
+    }
+    Ok(())
+}
+
+/// A database sequence along with its copy on the GPU.
+pub struct DeviceSequence<'a> {
+    pub sequence: &'a Sequence,
+    pub buffer: Buffer<u8>,
+}
+
+/// The database sequences that reside on the GPU (see `register_database`
+/// of the engines), so that aligning queries against them only copies the
+/// queries. The sequences are identified by their residues.
+#[derive(Default)]
+pub struct ResidentDatabase {
+    sequences: RwLock<Vec<(Vec<u8>, Buffer<u8>)>>,
+}
+
+impl ResidentDatabase {
+    /// Copies the given sequences to the GPU (using the given queue).
+    pub fn register(&self, queue: &Queue, sequences: &[Sequence]) -> Result<(), Error> {
+        let buffers = sequences.iter()
+            .map(|sequence| Ok((sequence.residues.clone(), gpu_buffer_from(queue, MEM_READ_ONLY, &sequence.residues)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        self.sequences.write().unwrap().extend(buffers);
+        Ok(())
+    }
 
-        // Read GPU buffers to CPU memory
-        let mut h = vec![0; size];
-        let mut p = vec![0; size];
+    /// The copy of the given sequence on the GPU, if it is registered.
+    pub fn get(&self, sequence: &Sequence) -> Option<Buffer<u8>> {
+        self.sequences.read().unwrap().iter()
+            .find(|(residues, _)| *residues == sequence.residues)
+            .map(|(_, buffer)| buffer.clone())
+    }
 
-        gpu_h.read(&mut h).enq().unwrap();
-        gpu_p.read(&mut p).enq().unwrap();
+    /// The given sequence along with its copy on the GPU, which
+    /// we copy now (using the given queue) unless it is registered.
+    pub fn upload<'a>(&self, queue: &Queue, sequence: &'a Sequence) -> Result<DeviceSequence<'a>, Error> {
+        let buffer = match self.get(sequence) {
+            Some(buffer) => buffer,
+            None => gpu_buffer_from(queue, MEM_READ_ONLY, &sequence.residues)?,
+        };
+        Ok(DeviceSequence { sequence, buffer })
+    }
+}
 
-        // Perform traceback stage (using the previously computed scoring matrix h)
+/// The OpenCL program of an engine, built once per score type by
+/// prepending `score.cl` with the corresponding `SCORE_BITS` (and
+/// `direction.cl` for the encoding of the traceback directions).
+pub struct ScorePrograms {
+    i8: Program,
+    i16: Program,
+    i32: Program,
+}
 
-        let mut i = (0..size).max_by_key(|&i| h[i]).unwrap();
-        let mut database_indices = Vec::new();
-        let mut query_indices = Vec::new();
+impl ScorePrograms {
+    /// Builds the program from the given sources for every score type.
+    pub fn build(context: &Context, sources: &[&str]) -> Result<Self, Error> {
+        let build = |bits| {
+            let mut builder = Program::builder();
+            builder.src(include_str!("score.cl")).src(include_str!("direction.cl")).cmplr_def("SCORE_BITS", bits);
+            for &src in sources {
+                builder.src(src);
+            }
+            builder.build(context).map_err(Error::from)
+        };
+        Ok(Self { i8: build(8)?, i16: build(16)?, i32: build(32)? })
+    }
 
-        while i > 0 && h[i] > 0 {
-            database_indices.push((i / width) - 1);
-            query_indices.push((i % width) - 1);
-            i = p[i] as usize;
+    /// The program for the given score type.
+    pub fn get<S: Score>(&self) -> &Program {
+        match S::BITS {
+            8 => &self.i8,
+            16 => &self.i16,
+            _ => &self.i32,
         }
+    }
+}
 
-        database_indices.reverse();
-        query_indices.reverse();
+/// Computes the score of the alignment and where it ends on the GPU by iterating
+/// over the diagonals (in a single launch). Since we only keep the right edge of
+/// the last strip, this needs linear memory (and is thus the same for all diagonal
+/// OpenCL engines). The programs need to include the kernel from `opencl_score.cl`.
+pub fn opencl_score_diagonals(programs: &ScorePrograms, queue: Queue, scoring: &ScoringScheme, gpu_matrix: &Buffer<i16>, mode: AlignmentMode, database: &DeviceSequence, query: &Sequence) -> Result<AlignmentScore, Error> {
+    // Start with 8-bit scores and widen them if the alignment saturates
+    opencl_score_diagonals_with::<i8>(programs.get::<i8>(), queue.clone(), scoring, gpu_matrix, mode, database, query).transpose()
+        .or_else(|| opencl_score_diagonals_with::<i16>(programs.get::<i16>(), queue.clone(), scoring, gpu_matrix, mode, database, query).transpose())
+        .or_else(|| opencl_score_diagonals_with::<i32>(programs.get::<i32>(), queue, scoring, gpu_matrix, mode, database, query).transpose())
+        .unwrap_or(Err(Error::ScoreOverflow))
+}
 
-        metrics.lock().unwrap().record_sequence_pair();
+/// Computes the score like `opencl_score_diagonals` using the given
+/// score type, or returns none if any value of h saturates.
+fn opencl_score_diagonals_with<S: Score + OclPrm>(program: &Program, queue: Queue, scoring: &ScoringScheme, gpu_matrix: &Buffer<i16>, mode: AlignmentMode, database: &DeviceSequence, query: &Sequence) -> Result<Option<AlignmentScore>, Error> {
+    if !S::fits(scoring) {
+        return Ok(None);
+    }
+
+    let (gpu_database, database) = (&database.buffer, database.sequence);
+
+    let n = database.len();
+    let m = query.len();
+    let height = n + 1;
+    let width = m + 1;
+
+    // Compute the borders (the topmost row and the leftmost column) of h
+    // and the initial values for tracking the end of the alignment on the
+    // CPU (see the CPU's score_diagonals for details)
+    let border_row: Vec<S> = (0..width).map(|j| mode.border(scoring, 0, j).0).collect();
+    let border_column: Vec<S> = (0..height).map(|i| mode.border(scoring, i, 0).0).collect();
+
+    if border_row.iter().chain(&border_column).any(|x| x.is_saturated()) {
+        return Ok(None);
+    }
+
+    let mut row_maxima = border_column.clone();
+    let mut row_maxima_j = vec![0u32; height];
+    let mut last_row = border_row.clone();
+    let mut last_column = border_column.clone();
 
-        AlignedPair::new(
-            AlignedSequence::new(database, database_indices),
-            AlignedSequence::new(query, query_indices),
-        )
+    let (first_maximum, first_maximum_j) = (0..width).map(|j| (border_row[j], j)).max_by_key(|&(x, _)| x).unwrap();
+    row_maxima[0] = first_maximum;
+    row_maxima_j[0] = first_maximum_j as u32;
+    last_row[0] = border_column[n];
+    last_column[0] = border_row[m];
+
+    // Allocate buffers on the GPU, including the right edge of the
+    // last strip of h and e (the database is already on the GPU).
+    let gpu_query = gpu_buffer_from(&queue, MEM_READ_ONLY, &query.residues)?;
+    let gpu_border_row: Buffer<S> = Buffer::builder().queue(queue.clone()).len(width).flags(MEM_READ_ONLY).copy_host_slice(&border_row).build()?;
+    let gpu_border_column: Buffer<S> = Buffer::builder().queue(queue.clone()).len(height).flags(MEM_READ_ONLY).copy_host_slice(&border_column).build()?;
+    let gpu_h_edge: Buffer<S> = Buffer::builder().queue(queue.clone()).len(height).build()?;
+    let gpu_e_edge: Buffer<S> = Buffer::builder().queue(queue.clone()).len(height).build()?;
+    let gpu_row_maxima: Buffer<S> = Buffer::builder().queue(queue.clone()).len(height).copy_host_slice(&row_maxima).build()?;
+    let gpu_row_maxima_j: Buffer<u32> = Buffer::builder().queue(queue.clone()).len(height).copy_host_slice(&row_maxima_j).build()?;
+    let gpu_last_row: Buffer<S> = Buffer::builder().queue(queue.clone()).len(width).copy_host_slice(&last_row).build()?;
+    let gpu_last_column: Buffer<S> = Buffer::builder().queue(queue.clone()).len(height).copy_host_slice(&last_column).build()?;
+    let gpu_saturated: Buffer<i32> = Buffer::builder().queue(queue.clone()).len(1).copy_host_slice(&[0]).build()?;
+
+    // Create the kernel, whose work-group walks over all diagonals
+    // (see `opencl_score.cl`), and enqueue it once.
+    let lanes = wavefront_size(queue.device(), m)?;
+    let kernel = Kernel::builder()
+        .name("smith_waterman_wavefront_score")
+        .program(program)
+        .queue(queue)
+        .global_work_size(lanes)
+        .local_work_size(lanes)
+        .arg(n as u32)
+        .arg(m as u32)
+        .arg(S::saturating_from(scoring.gap_open as i32))
+        .arg(S::saturating_from(scoring.gap_extend as i32))
+        .arg(mode.min_score::<S>())
+        .arg(scoring.matrix.size() as u32)
+        .arg(gpu_matrix)
+        .arg(gpu_database)
+        .arg(&gpu_query)
+        .arg(&gpu_border_row)
+        .arg(&gpu_border_column)
+        .arg(&gpu_h_edge)
+        .arg(&gpu_e_edge)
+        .arg(&gpu_row_maxima)
+        .arg(&gpu_row_maxima_j)
+        .arg(&gpu_last_row)
+        .arg(&gpu_last_column)
+        .arg(&gpu_saturated)
+        .arg_local::<S>(2 * lanes)
+        .arg_local::<S>(2 * lanes)
+        .build()?;
+
+    unsafe { kernel.enq()?; }
+
+    // Read GPU buffers to CPU memory
+    let mut saturated = [0];
+    gpu_saturated.read(&mut saturated[..]).enq()?;
+    if saturated[0] != 0 {
+        return Ok(None);
+    }
+
+    gpu_row_maxima.read(&mut row_maxima).enq()?;
+    gpu_row_maxima_j.read(&mut row_maxima_j).enq()?;
+    gpu_last_row.read(&mut last_row).enq()?;
+    gpu_last_column.read(&mut last_column).enq()?;
+
+    let row_maxima: Vec<_> = row_maxima.into_iter().zip(row_maxima_j).map(|(x, j)| (x, j as usize)).collect();
+    Ok(Some(mode.find_end(&row_maxima, &last_row, &last_column)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{alphabet::Alphabet, metrics::Metrics, model::Sequence};
+    use crate::engine::{Engine, NaiveEngine, OpenCLDeviceSelector, OpenCLDeviceType, ScoringScheme, SubstitutionMatrix};
+    use crate::engine::testing::{MODES, assert_alignment_consistent};
+
+    use super::OpenCLDiagonalEngine;
+
+    #[test]
+    fn aligns_empty_sequences() {
+        // Without an OpenCL device, there is nothing to test
+        let selector = OpenCLDeviceSelector { platform_index: 0, device_type: OpenCLDeviceType::All, device_index: 0 };
+        let Ok(opencl) = selector.select() else { return };
+
+        let metrics = Arc::new(Mutex::new(Metrics::new()));
+        let scoring = ScoringScheme::new(3, 1, SubstitutionMatrix::uniform(Alphabet::Dna, 2, -3));
+        let sequences = [b"".as_slice(), b"ACGT"].map(|raw| Sequence::parse("Sequence", Alphabet::Dna, raw).unwrap());
+
+        for mode in MODES {
+            let naive = NaiveEngine::new(scoring.clone(), mode);
+            let engine = OpenCLDiagonalEngine::new(opencl, scoring.clone(), mode).unwrap();
+
+            for (database, query) in [(0, 0), (0, 1), (1, 0)].map(|(d, q)| (&sequences[d], &sequences[q])) {
+                let expected = naive.score(database, query, &metrics).unwrap();
+                assert_eq!(engine.score(database, query, &metrics).unwrap(), expected, "{}", mode);
+
+                let aligned = engine.align(database, query, &metrics).unwrap();
+                assert_eq!(aligned.score, expected.score, "{}", mode);
+                assert_alignment_consistent(&scoring, mode, &aligned);
+            }
+        }
     }
 }
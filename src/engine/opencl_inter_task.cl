@@ -0,0 +1,129 @@
+// Considers the given cell as the end of the alignment. Ties are resolved
+// towards the last cell considered, like in the host's find_end.
+#define CONSIDER_SCORE(value, i, j) \
+    if (!found || (value) >= best) { \
+        found = true; \
+        best = (value); \
+        best_i = (i); \
+        best_j = (j); \
+    }
+
+// Computes the score of a query's alignment against the database (and where
+// it ends) per work-item, thus a single launch scores many pairs. The queries
+// are packed into a single buffer, where query q spans the residues from
+// offsets[q] to offsets[q + 1] (and its row of h and f the cells from
+// offsets[q] + q to offsets[q + 1] + q). Mostly a translation of the CPU's
+// score_rows. If any value of h saturates, the kernel sets the query's
+// saturated flag.
+__kernel void smith_waterman_inter_task(
+    uint n,
+    uint queries,
+    score_t gap_open,
+    score_t gap_extend,
+    score_t min_score,
+    uint matrix_size,
+    __global short *matrix,
+    uint local_alignment,
+    uint query_trailing,
+    uint database_trailing,
+    __global uchar *database,
+    __global uchar *residues,
+    __global uint *offsets,
+    __global score_t *border_row,
+    __global score_t *border_column,
+    __global score_t *h,
+    __global score_t *f,
+    __global int *scores,
+    __global uint *database_ends,
+    __global uint *query_ends,
+    __global int *saturated
+) {
+    uint q = get_global_id(0);
+    if (q >= queries) {
+        return;
+    }
+
+    uint m = offsets[q + 1] - offsets[q];
+    __global uchar *query = residues + offsets[q];
+    __global score_t *h_row = h + offsets[q] + q;
+    __global score_t *f_row = f + offsets[q] + q;
+
+    bool found = false;
+    bool is_saturated = false;
+    score_t best = 0;
+    uint best_i = 0;
+    uint best_j = 0;
+
+    // We only keep a single row of h and f, which we update in-place while
+    // iterating from left to right, starting with the topmost row (the border)
+    for (uint j = 0; j <= m; j++) {
+        h_row[j] = border_row[j];
+        f_row[j] = (score_t) NEGATIVE_INFINITY;
+        is_saturated |= IS_SATURATED(border_row[j]);
+
+        if (local_alignment) {
+            CONSIDER_SCORE(border_row[j], 0, j);
+        }
+    }
+
+    if (query_trailing) {
+        CONSIDER_SCORE(border_row[m], 0, m);
+    }
+
+    for (uint i = 1; i <= n; i++) {
+        // Remember the value above left before we overwrite it
+        score_t h_above_left = h_row[0];
+        score_t h_left = border_column[i];
+        score_t e_left = (score_t) NEGATIVE_INFINITY;
+        uint residue = database[i - 1] * matrix_size;
+
+        h_row[0] = h_left;
+        is_saturated |= IS_SATURATED(h_left);
+
+        if (local_alignment) {
+            CONSIDER_SCORE(h_left, i, 0);
+        }
+
+        for (uint j = 1; j <= m; j++) {
+            // Compute helper values and value (the host makes
+            // sure that the weights fit into the score type)
+            score_t e_here = max(sub_sat(e_left, gap_extend), sub_sat(h_left, gap_open));
+            score_t f_here = max(sub_sat(f_row[j], gap_extend), sub_sat(h_row[j], gap_open));
+            score_t h_here = add_sat(h_above_left, (score_t) matrix[residue + query[j - 1]]);
+            h_here = max(h_here, e_here);
+            h_here = max(h_here, f_here);
+            h_here = max(h_here, min_score);
+            is_saturated |= IS_SATURATED(h_here);
+
+            if (local_alignment) {
+                CONSIDER_SCORE(h_here, i, j);
+            }
+
+            h_above_left = h_row[j];
+            h_row[j] = h_here;
+            f_row[j] = f_here;
+            h_left = h_here;
+            e_left = e_here;
+        }
+
+        if (query_trailing) {
+            CONSIDER_SCORE(h_left, i, m);
+        }
+    }
+
+    // With free end gaps, the alignment may also end in the last row,
+    // otherwise it ends in the bottom right corner (see FreeEndGaps::end)
+    if (!local_alignment) {
+        if (database_trailing) {
+            for (uint j = 0; j <= m; j++) {
+                CONSIDER_SCORE(h_row[j], n, j);
+            }
+        }
+        CONSIDER_SCORE(h_row[m], n, m);
+    }
+
+    scores[q] = best;
+    database_ends[q] = best_i;
+    query_ends[q] = best_j;
+    saturated[q] = is_saturated;
+}
@@ -0,0 +1,211 @@
+use std::{slice, sync::{Arc, Mutex}};
+use ocl::{Buffer, OclPrm, core::{MEM_READ_ONLY, MEM_READ_WRITE, MEM_WRITE_ONLY}, Kernel};
+
+use crate::{model::{Sequence, AlignedPair, AlignmentScore}, metrics::Metrics, error::Error};
+
+use super::{AlignmentMode, Engine, OpenCLDevice, OpenCLDiagonalEngine, Score, ScoringScheme, gpu_buffer, gpu_buffer_from, opencl_device_label};
+
+/// An engine that scores many queries against the same database
+/// sequence at once with OpenCL on the GPU (similar to the
+/// inter-sequence engine on the CPU). Every work-item aligns
+/// a whole pair, thus a single launch scores an entire batch,
+/// which keeps the GPU busy even with short sequences.
+///
+/// The queries are first scored with 8-bit values. Those whose
+/// values saturate are then rescored with 16 and, if needed, 32 bits.
+pub struct OpenCLInterTaskEngine {
+    scoring: ScoringScheme,
+    mode: AlignmentMode,
+    /// The engine performing the traceback, whose context, substitution
+    /// matrix and database on the GPU we share (its programs include the
+    /// kernel from `opencl_inter_task.cl`).
+    diagonal: OpenCLDiagonalEngine,
+}
+
+impl OpenCLInterTaskEngine {
+    pub fn new(opencl: OpenCLDevice, scoring: ScoringScheme, mode: AlignmentMode) -> Result<Self, Error> {
+        // The OpenCL program source code.
+        let program_src = include_str!("opencl_inter_task.cl");
+
+        let diagonal = OpenCLDiagonalEngine::with_sources(opencl, scoring.clone(), mode, &[program_src])?;
+
+        Ok(Self { scoring, mode, diagonal })
+    }
+
+    /// Copies the given database sequences to the GPU, where they stay for the
+    /// lifetime of the engine (see `OpenCLDiagonalEngine::register_database`).
+    pub fn register_database(&self, sequences: &[Sequence]) -> Result<(), Error> {
+        self.diagonal.register_database(sequences)
+    }
+
+    /// Computes the scores of the given queries' alignments against
+    /// the database, starting with 8-bit scores and rescoring the
+    /// queries that saturate with wider ones.
+    fn score_all(&self, database: &Sequence, queries: &[Sequence]) -> Vec<Result<AlignmentScore, Error>> {
+        let mut scores = vec![None; queries.len()];
+
+        let launch = self.rescore::<i8>(database, queries, &mut scores)
+            .and_then(|()| self.rescore::<i16>(database, queries, &mut scores))
+            .and_then(|()| self.rescore::<i32>(database, queries, &mut scores));
+
+        // The scores that are still missing either overflow even with
+        // 32-bit scores or could not be computed since a launch failed
+        scores.into_iter().map(|score| match (score, &launch) {
+            (Some(score), _) => Ok(score),
+            (None, Ok(())) => Err(Error::ScoreOverflow),
+            (None, Err(e)) => Err(e.clone()),
+        }).collect()
+    }
+
+    /// Scores the queries that have no score yet with the given score type.
+    fn rescore<S: Score + OclPrm>(&self, database: &Sequence, queries: &[Sequence], scores: &mut [Option<AlignmentScore>]) -> Result<(), Error> {
+        let missing: Vec<_> = (0..queries.len()).filter(|&q| scores[q].is_none()).collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        let pending: Vec<_> = missing.iter().map(|&q| &queries[q]).collect();
+        for (q, score) in missing.into_iter().zip(self.score_with::<S>(database, &pending)?) {
+            scores[q] = score;
+        }
+        Ok(())
+    }
+
+    /// Computes the scores of the given queries' alignments against the database
+    /// with the given score type in a single launch. Queries whose values saturate
+    /// get no score.
+    fn score_with<S: Score + OclPrm>(&self, database: &Sequence, queries: &[&Sequence]) -> Result<Vec<Option<AlignmentScore>>, Error> {
+        if !S::fits(&self.scoring) {
+            return Ok(vec![None; queries.len()]);
+        }
+
+        let n = database.len();
+        let count = queries.len();
+
+        // Pack the queries from the longest to the shortest one, so that
+        // neighboring work-items (which run in lockstep) take similarly long.
+        let mut order: Vec<_> = (0..count).collect();
+        order.sort_by_key(|&q| std::cmp::Reverse(queries[q].len()));
+
+        let mut residues = Vec::new();
+        let mut offsets = vec![0];
+        for &q in &order {
+            residues.extend_from_slice(&queries[q].residues);
+            offsets.push(residues.len());
+        }
+
+        // The kernel indexes the packed queries (and the rows of h and f,
+        // which hold an extra value per query) with 32 bits
+        if u32::try_from(residues.len() + count).is_err() {
+            return Err(Error::MatrixTooLarge { database: n, query: residues.len() });
+        }
+        let offsets: Vec<_> = offsets.into_iter().map(|offset| offset as u32).collect();
+
+        // Compute the borders (the topmost row and the leftmost column) of h,
+        // which are the same for all queries (up to their lengths)
+        let max_m = queries.iter().map(|query| query.len()).max().unwrap_or(0);
+        let border_row: Vec<S> = (0..=max_m).map(|j| self.mode.border(&self.scoring, 0, j).0).collect();
+        let border_column: Vec<S> = (0..=n).map(|i| self.mode.border(&self.scoring, i, 0).0).collect();
+
+        // Copy the queries to the GPU (and the database, unless it is registered)
+        // and allocate the rows of h and f along with the results
+        let queue = self.diagonal.queue()?;
+        let gpu_database = self.diagonal.resident().upload(&queue, database)?;
+        let gpu_residues = gpu_buffer_from(&queue, MEM_READ_ONLY, &residues)?;
+        let gpu_offsets = gpu_buffer_from(&queue, MEM_READ_ONLY, &offsets)?;
+        let gpu_border_row = gpu_buffer_from(&queue, MEM_READ_ONLY, &border_row)?;
+        let gpu_border_column = gpu_buffer_from(&queue, MEM_READ_ONLY, &border_column)?;
+        let gpu_h: Buffer<S> = gpu_buffer(&queue, MEM_READ_WRITE, residues.len() + count)?;
+        let gpu_f: Buffer<S> = gpu_buffer(&queue, MEM_READ_WRITE, residues.len() + count)?;
+        let gpu_scores: Buffer<i32> = gpu_buffer(&queue, MEM_WRITE_ONLY, count)?;
+        let gpu_database_ends: Buffer<u32> = gpu_buffer(&queue, MEM_WRITE_ONLY, count)?;
+        let gpu_query_ends: Buffer<u32> = gpu_buffer(&queue, MEM_WRITE_ONLY, count)?;
+        let gpu_saturated: Buffer<i32> = gpu_buffer(&queue, MEM_WRITE_ONLY, count)?;
+
+        // Create the kernel with a work-item per query and enqueue it once.
+        let gaps = self.mode.free_end_gaps();
+        let kernel = Kernel::builder()
+            .name("smith_waterman_inter_task")
+            .program(self.diagonal.programs().get::<S>())
+            .queue(queue.clone())
+            .global_work_size(count.max(1))
+            .arg(n as u32)
+            .arg(count as u32)
+            .arg(S::saturating_from(self.scoring.gap_open as i32))
+            .arg(S::saturating_from(self.scoring.gap_extend as i32))
+            .arg(self.mode.min_score::<S>())
+            .arg(self.scoring.matrix.size() as u32)
+            .arg(self.diagonal.gpu_matrix())
+            .arg(gaps.is_none() as u32)
+            .arg(gaps.is_some_and(|gaps| gaps.query_trailing) as u32)
+            .arg(gaps.is_some_and(|gaps| gaps.database_trailing) as u32)
+            .arg(&gpu_database.buffer)
+            .arg(&gpu_residues)
+            .arg(&gpu_offsets)
+            .arg(&gpu_border_row)
+            .arg(&gpu_border_column)
+            .arg(&gpu_h)
+            .arg(&gpu_f)
+            .arg(&gpu_scores)
+            .arg(&gpu_database_ends)
+            .arg(&gpu_query_ends)
+            .arg(&gpu_saturated)
+            .build()?;
+
+        unsafe { kernel.enq()?; }
+
+        // Read GPU buffers to CPU memory
+        let mut scores = vec![0; count];
+        let mut database_ends = vec![0; count];
+        let mut query_ends = vec![0; count];
+        let mut saturated = vec![0; count];
+
+        if count > 0 {
+            gpu_scores.read(&mut scores).enq()?;
+            gpu_database_ends.read(&mut database_ends).enq()?;
+            gpu_query_ends.read(&mut query_ends).enq()?;
+            gpu_saturated.read(&mut saturated).enq()?;
+        }
+
+        // Restore the order of the queries
+        let mut results = vec![None; count];
+        for (k, q) in order.into_iter().enumerate() {
+            if saturated[k] == 0 {
+                results[q] = Some(AlignmentScore { score: scores[k], database_end: database_ends[k] as usize, query_end: query_ends[k] as usize });
+            }
+        }
+        Ok(results)
+    }
+}
+
+impl Engine for OpenCLInterTaskEngine {
+    fn name(&self) -> String {
+        format!("OpenCL Inter-Task ({})", opencl_device_label(self.diagonal.device()))
+    }
+
+    fn align<'a>(&self, database: &'a Sequence, query: &'a Sequence, metrics: &Arc<Mutex<Metrics>>) -> Result<AlignedPair<'a>, Error> {
+        // The traceback needs the entire matrix of a pair,
+        // thus we use the diagonal engine for the alignment.
+        self.diagonal.align(database, query, metrics)
+    }
+
+    fn align_batch<'a>(&self, database: &'a Sequence, queries: &'a [Sequence], metrics: &Arc<Mutex<Metrics>>) -> Vec<Result<AlignedPair<'a>, Error>> {
+        self.diagonal.align_batch(database, queries, metrics)
+    }
+
+    fn score(&self, database: &Sequence, query: &Sequence, metrics: &Arc<Mutex<Metrics>>) -> Result<AlignmentScore, Error> {
+        self.score_batch(database, slice::from_ref(query), metrics).pop().unwrap()
+    }
+
+    fn score_batch(&self, database: &Sequence, queries: &[Sequence], metrics: &Arc<Mutex<Metrics>>) -> Vec<Result<AlignmentScore, Error>> {
+        let scores = self.score_all(database, queries);
+
+        let mut metrics = metrics.lock().unwrap();
+        for query in queries {
+            metrics.record_cell_updates(4 * (database.len() + 1) * (query.len() + 1));
+            metrics.record_sequence_pair();
+        }
+
+        scores
+    }
+}
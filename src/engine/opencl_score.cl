@@ -0,0 +1,112 @@
+// Computes the score of the alignment on the GPU in a single launch, only
+// keeping the values at the edges of the strips to compute the score. Like
+// `opencl_wavefront.cl`, a single work-group walks over the matrix in vertical
+// strips (one column per work-item and an anti-diagonal of the strip per step),
+// passing the cells on to the right through local memory. Mostly a translation
+// of the inner loop from the CPU's score_diagonals. If any value of h
+// saturates, the kernel sets the saturated flag.
+__kernel void smith_waterman_wavefront_score(
+    uint n,
+    uint m,
+    score_t gap_open,
+    score_t gap_extend,
+    score_t min_score,
+    uint matrix_size,
+    __global short *matrix,
+    __global uchar *database,
+    __global uchar *query,
+    __global score_t *border_row,
+    __global score_t *border_column,
+    __global score_t *h_edge,
+    __global score_t *e_edge,
+    __global score_t *row_maxima,
+    __global uint *row_maxima_j,
+    __global score_t *last_row,
+    __global score_t *last_column,
+    __global int *saturated,
+    __local score_t *h_lefts,
+    __local score_t *e_lefts
+) {
+    uint lanes = get_local_size(0);
+    uint lane = get_local_id(0);
+
+    for (uint strip = 1; strip <= m; strip += lanes) {
+        uint j = strip + lane;
+        bool in_strip = j <= m;
+
+        // Start with the topmost row (the border)
+        score_t h_above = 0;
+        score_t f_above = (score_t) NEGATIVE_INFINITY;
+        score_t h_above_left = 0;
+
+        if (in_strip) {
+            h_above = border_row[j];
+            h_above_left = border_row[j - 1];
+        }
+
+        // In step t, the work-item computes row i = t + 1 - lane (see `opencl_wavefront.cl`)
+        for (uint t = 0; t < n + lanes - 1; t++) {
+            uint i = t + 1 - lane;
+            uint current = (t % 2) * lanes;
+            uint previous = ((t + 1) % 2) * lanes;
+
+            if (in_strip && t >= lane && i <= n) {
+                // Fetch the cell to the left, either from the neighbor or (at the left
+                // edge of the strip) from the right edge of the last strip or the border
+                score_t h_left;
+                score_t e_left;
+
+                if (lane > 0) {
+                    h_left = h_lefts[previous + lane - 1];
+                    e_left = e_lefts[previous + lane - 1];
+                } else if (strip > 1) {
+                    h_left = h_edge[i];
+                    e_left = e_edge[i];
+                } else {
+                    h_left = border_column[i];
+                    e_left = (score_t) NEGATIVE_INFINITY;
+                }
+
+                // Compute helper values and value (the host makes
+                // sure that the weights fit into the score type)
+                score_t e_here = max(sub_sat(e_left, gap_extend), sub_sat(h_left, gap_open));
+                score_t f_here = max(sub_sat(f_above, gap_extend), sub_sat(h_above, gap_open));
+                score_t h_here = add_sat(h_above_left, (score_t) matrix[database[i - 1] * matrix_size + query[j - 1]]);
+                h_here = max(h_here, e_here);
+                h_here = max(h_here, f_here);
+                h_here = max(h_here, min_score);
+
+                if (IS_SATURATED(h_here)) {
+                    *saturated = 1;
+                }
+
+                // Since every row is visited from left to right (one cell per step),
+                // we can track the maxima without further synchronization.
+                if (h_here >= row_maxima[i]) {
+                    row_maxima[i] = h_here;
+                    row_maxima_j[i] = j;
+                }
+                if (i == n) {
+                    last_row[j] = h_here;
+                }
+                if (j == m) {
+                    last_column[i] = h_here;
+                }
+
+                // Pass the cell on to the right neighbor (or the next strip) and move down
+                if (lane == lanes - 1) {
+                    h_edge[i] = h_here;
+                    e_edge[i] = e_here;
+                }
+                h_lefts[current + lane] = h_here;
+                e_lefts[current + lane] = e_here;
+                h_above_left = h_left;
+                h_above = h_here;
+                f_above = f_here;
+            }
+
+            // Wait for the step to finish
+            barrier(CLK_LOCAL_MEM_FENCE | CLK_GLOBAL_MEM_FENCE);
+        }
+    }
+}
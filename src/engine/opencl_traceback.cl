@@ -0,0 +1,173 @@
+// The operations of an alignment (in the order of the Operation type on the host).
+#define OPERATION_MATCH 0
+#define OPERATION_MISMATCH 1
+#define OPERATION_INSERTION 2
+#define OPERATION_DELETION 3
+
+// The matrix (from Gotoh's algorithm) that the traceback is currently following.
+#define STATE_H 0
+#define STATE_E 1
+#define STATE_F 2
+
+// Finds the maximum of h along with its index (preferring the last one, like
+// max_by_key on the host) and whether any value of h saturates. Every work-item
+// first reduces the values at its stride of the matrix, then every work-group
+// reduces these in local memory, leaving one value per work-group to the host.
+__kernel void smith_waterman_maximum(
+    uint size,
+    __global score_t *h,
+    __global score_t *maxima,
+    __global uint *maximum_indices,
+    __global int *saturated,
+    __local score_t *local_maxima,
+    __local uint *local_maximum_indices,
+    __local int *local_saturated
+) {
+    uint lanes = get_local_size(0);
+    uint lane = get_local_id(0);
+
+    score_t maximum = SCORE_MIN;
+    uint maximum_index = 0;
+    int is_saturated = 0;
+
+    for (uint index = get_global_id(0); index < size; index += get_global_size(0)) {
+        score_t value = h[index];
+        is_saturated |= IS_SATURATED(value);
+
+        if (value >= maximum) {
+            maximum = value;
+            maximum_index = index;
+        }
+    }
+
+    local_maxima[lane] = maximum;
+    local_maximum_indices[lane] = maximum_index;
+    local_saturated[lane] = is_saturated;
+    barrier(CLK_LOCAL_MEM_FENCE);
+
+    // Merge the values of neighboring work-items, doubling the distance in
+    // every step until the first work-item holds the work-group's maximum
+    for (uint stride = 1; stride < lanes; stride *= 2) {
+        uint other = lane + stride;
+
+        if (lane % (2 * stride) == 0 && other < lanes) {
+            if (local_maxima[other] > local_maxima[lane] || (local_maxima[other] == local_maxima[lane] && local_maximum_indices[other] > local_maximum_indices[lane])) {
+                local_maxima[lane] = local_maxima[other];
+                local_maximum_indices[lane] = local_maximum_indices[other];
+            }
+            local_saturated[lane] |= local_saturated[other];
+        }
+
+        barrier(CLK_LOCAL_MEM_FENCE);
+    }
+
+    if (lane == 0) {
+        uint group = get_group_id(0);
+        maxima[group] = local_maxima[0];
+        maximum_indices[group] = local_maximum_indices[0];
+        saturated[group] = local_saturated[0];
+    }
+}
+
+// Considers the given cell as the end of the alignment. Ties are resolved
+// towards the last cell considered, like in FreeEndGaps::end on the host.
+#define CONSIDER(i, j) \
+    if (!found || h[INDEX(i, j)] >= h[INDEX(end_i, end_j)]) { \
+        found = true; \
+        end_i = (i); \
+        end_j = (j); \
+    }
+
+// Traces back the alignment on the GPU (with a single work-item), thus only
+// the path needs to be copied back rather than the matrices. Mostly a translation
+// of the host's traceback (see there for details). Local alignments end at the
+// given cell (the maximum of h), whereas for (semi-)global alignments, we look
+// for the end in the last column and row (depending on the free trailing gaps).
+// The path is stored from the end to the start, along with the score, the
+// start and the length of the alignment in trace.
+__kernel void smith_waterman_traceback(
+    uint height,
+    uint width,
+    uint end_i,
+    uint end_j,
+    uint local_alignment,
+    uint database_leading,
+    uint database_trailing,
+    uint query_leading,
+    uint query_trailing,
+    __global uchar *database,
+    __global uchar *query,
+    __global score_t *h,
+    __global uchar *p,
+    __global uchar *path,
+    __global int *trace
+) {
+    uint n = height - 1;
+    uint m = width - 1;
+
+    if (!local_alignment) {
+        bool found = false;
+
+        if (query_trailing) {
+            for (uint i = 0; i <= n; i++) {
+                CONSIDER(i, m);
+            }
+        }
+        if (database_trailing) {
+            for (uint j = 0; j <= m; j++) {
+                CONSIDER(n, j);
+            }
+        }
+        CONSIDER(n, m);
+    }
+
+    uint i = end_i;
+    uint j = end_j;
+    uint length = 0;
+    uchar state = STATE_H;
+
+    for (;;) {
+        score_t h_here = h[INDEX(i, j)];
+        uchar direction = p[INDEX(i, j)];
+
+        if (!local_alignment && !database_leading && i == 0 && j > 0) {
+            // The border of (semi-)global alignments is a leading gap
+            path[length++] = OPERATION_INSERTION;
+            j--;
+        } else if (!local_alignment && !query_leading && j == 0 && i > 0) {
+            path[length++] = OPERATION_DELETION;
+            i--;
+        } else if (state == STATE_H && i > 0 && j > 0 && (!local_alignment || h_here > 0)) {
+            uchar origin = direction & ORIGIN_MASK;
+
+            if (origin == ORIGIN_LEFT) {
+                state = STATE_E;
+            } else if (origin == ORIGIN_ABOVE) {
+                state = STATE_F;
+            } else {
+                path[length++] = database[i - 1] == query[j - 1] ? OPERATION_MATCH : OPERATION_MISMATCH;
+                i--;
+                j--;
+            }
+        } else if (state == STATE_E && j > 0) {
+            path[length++] = OPERATION_INSERTION;
+            if (!(direction & E_EXTENDED)) {
+                state = STATE_H;
+            }
+            j--;
+        } else if (state == STATE_F && i > 0) {
+            path[length++] = OPERATION_DELETION;
+            if (!(direction & F_EXTENDED)) {
+                state = STATE_H;
+            }
+            i--;
+        } else {
+            break;
+        }
+    }
+
+    trace[0] = h[INDEX(end_i, end_j)];
+    trace[1] = i;
+    trace[2] = j;
+    trace[3] = length;
+}
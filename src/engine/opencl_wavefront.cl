@@ -0,0 +1,132 @@
+// Computes the Smith-Waterman matrices on the GPU in a single launch.
+// A single work-group walks over the matrix in vertical strips with one
+// column per work-item, computing an anti-diagonal of the strip per step.
+// Every work-item keeps the cells above in private memory and receives the
+// cells to its left from its neighbor through local memory, thus the
+// work-items only synchronize (with a barrier) once per step. The layout
+// of the matrices is given by the INDEX(i, j) macro, which the engine's
+// source defines (in terms of height and width). The borders of h are
+// passed separately (like in `opencl_score.cl`), so the host does not
+// have to copy the matrices to the GPU.
+__kernel void smith_waterman_wavefront(
+    uint height,
+    uint width,
+    score_t gap_open,
+    score_t gap_extend,
+    score_t min_score,
+    uint matrix_size,
+    __global short *matrix,
+    __global uchar *database,
+    __global uchar *query,
+    __global score_t *border_row,
+    __global score_t *border_column,
+    __global score_t *h,
+    __global score_t *e,
+    __global score_t *f,
+    __global uchar *p,
+    __local score_t *h_lefts,
+    __local score_t *e_lefts
+) {
+    uint lanes = get_local_size(0);
+    uint lane = get_local_id(0);
+
+    // Fill in the borders of the matrices (h for the traceback, f along the
+    // topmost row and e along the leftmost column for the first strip)
+    for (uint j = lane; j < width; j += lanes) {
+        h[INDEX(0, j)] = border_row[j];
+        f[INDEX(0, j)] = NEGATIVE_INFINITY;
+    }
+    for (uint i = lane; i < height; i += lanes) {
+        h[INDEX(i, 0)] = border_column[i];
+        e[INDEX(i, 0)] = NEGATIVE_INFINITY;
+    }
+    barrier(CLK_GLOBAL_MEM_FENCE);
+
+    for (uint strip = 1; strip < width; strip += lanes) {
+        uint j = strip + lane;
+        bool in_strip = j < width;
+
+        // Start with the topmost row (the border)
+        score_t h_above = 0;
+        score_t f_above = 0;
+        score_t h_above_left = 0;
+
+        if (in_strip) {
+            h_above = h[INDEX(0, j)];
+            f_above = f[INDEX(0, j)];
+            h_above_left = h[INDEX(0, j - 1)];
+        }
+
+        // In step t, the work-item computes row i = t + 1 - lane, i.e. the cell right
+        // of the one its left neighbor computed in the previous step. The values
+        // of the steps alternate between the two halves of the local buffers.
+        for (uint t = 0; t < height + lanes - 2; t++) {
+            uint i = t + 1 - lane;
+            uint current = (t % 2) * lanes;
+            uint previous = ((t + 1) % 2) * lanes;
+
+            if (in_strip && t >= lane && i < height) {
+                // Fetch the cell to the left, either from the neighbor
+                // or (at the left edge of the strip) from the matrices
+                score_t h_left;
+                score_t e_left;
+
+                if (lane == 0) {
+                    h_left = h[INDEX(i, j - 1)];
+                    e_left = e[INDEX(i, j - 1)];
+                } else {
+                    h_left = h_lefts[previous + lane - 1];
+                    e_left = e_lefts[previous + lane - 1];
+                }
+
+                // Compute helper values and remember whether they extend
+                // a gap (we need this later for the traceback phase, too)
+                score_t e_extend = sub_sat(e_left, gap_extend);
+                score_t e_open = sub_sat(h_left, gap_open);
+                score_t f_extend = sub_sat(f_above, gap_extend);
+                score_t f_open = sub_sat(h_above, gap_open);
+                score_t e_here = max(e_extend, e_open);
+                score_t f_here = max(f_extend, f_open);
+
+                // Compute value and remember the direction the maximum came from
+                // (we need this later for the traceback phase)
+                // (the host makes sure that the weights fit into the score type)
+                score_t from_above_left = add_sat(h_above_left, (score_t) matrix[database[i - 1] * matrix_size + query[j - 1]]);
+                uchar max_origin = ORIGIN_START;
+                score_t max_value = min_score;
+
+                if (from_above_left >= max_value) {
+                    max_origin = ORIGIN_DIAGONAL;
+                    max_value = from_above_left;
+                }
+
+                if (e_here >= max_value) {
+                    max_origin = ORIGIN_LEFT;
+                    max_value = e_here;
+                }
+
+                if (f_here >= max_value) {
+                    max_origin = ORIGIN_ABOVE;
+                    max_value = f_here;
+                }
+
+                uint here = INDEX(i, j);
+                h[here] = max_value;
+                e[here] = e_here;
+                f[here] = f_here;
+                p[here] = DIRECTION(max_origin, e_extend > e_open, f_extend > f_open);
+
+                // Pass the cell on to the right neighbor and move down
+                h_lefts[current + lane] = max_value;
+                e_lefts[current + lane] = e_here;
+                h_above_left = h_left;
+                h_above = max_value;
+                f_above = f_here;
+            }
+
+            // Wait for the step to finish (including the writes to the matrices,
+            // which the left edge of the next strip reads)
+            barrier(CLK_LOCAL_MEM_FENCE | CLK_GLOBAL_MEM_FENCE);
+        }
+    }
+}
@@ -1,56 +1,73 @@
 use std::sync::{Arc, Mutex};
 use rayon::prelude::*;
 
-use crate::{model::{Sequence, AlignedPair, AlignedSequence}, metrics::Metrics, utils::UnsafeSlice};
+use crate::{model::{Sequence, AlignedPair, AlignmentScore}, metrics::Metrics, error::Error, utils::{Pool, UnsafeSlice}};
 
-use super::{Engine, G_INIT, G_EXT, WEIGHT_IF_EQ};
+use super::{AlignmentMode, DiagonalMajorLayout, Direction, Engine, Origin, Score, ScoringScheme, TracebackCell, Workspace, matrix_size, resized, score_diagonals, traceback};
 
 /// An engine that computes alignments using the
 /// Smith-Waterman-Algorithm with parallelized
 /// diagonals on the CPU. This variant additionally
 /// uses a diagonal-major layout of the matrix for
 /// better cache performance.
-pub struct OptimizedDiagonalEngine;
+pub struct OptimizedDiagonalEngine {
+    scoring: ScoringScheme,
+    mode: AlignmentMode,
+    workspaces: Pool,
+}
 
 impl OptimizedDiagonalEngine {
-    fn weight(d: u8, q: u8) -> i16 {
-        if d == q { WEIGHT_IF_EQ } else { -WEIGHT_IF_EQ }
+    pub fn new(scoring: ScoringScheme, mode: AlignmentMode) -> Self {
+        Self { scoring, mode, workspaces: Pool::new() }
     }
-}
 
-impl Engine for OptimizedDiagonalEngine {
-    fn name(&self) -> String {
-        "Optimized Diagonal (CPU)".to_owned()
-    }
+    /// Aligns the given two sequences using the given score type,
+    /// or returns none if any value of h saturates.
+    fn align_with<'a, S: Score>(&self, database: &'a Sequence, query: &'a Sequence) -> Result<Option<AlignedPair<'a>>, Error> {
+        if !S::fits(&self.scoring) {
+            return Ok(None);
+        }
 
-    fn align<'a>(&self, database: &'a Sequence, query: &'a Sequence, metrics: &Arc<Mutex<Metrics>>) -> AlignedPair<'a> {
         let n = database.len();
         let m = query.len();
         let height = n + 1;
         let width = m + 1;
-        let size = height * width;
+        let size = matrix_size(n, m)?;
 
-        // Create scoring matrix h, helper matrices e and f and a
-        // helper matrix p that tracks the previous index.
+        // Create scoring matrix h, helper matrices e and f, a
+        // helper matrix p that tracks the direction the value came
+        // from and whether e and f extend a gap (rather than opening one).
 
-        let mut h = vec![0; size];
-        let mut e = vec![0; size];
-        let mut f = vec![0; size];
-        let mut p = vec![0; size];
+        let mut workspace = self.workspaces.take(Workspace::<S>::default);
+        let workspace = &mut *workspace;
+        let h = resized(&mut workspace.h, size)?;
+        let e = resized(&mut workspace.e, size)?;
+        let f = resized(&mut workspace.f, size)?;
+        let p = resized(&mut workspace.p, size)?;
 
-        // Additionally, create helper matrices is and js that
-        // map each index in our new diagonal-major scheme to
-        // the original index.
+        // We lay out the matrices in diagonal-major order, which
+        // also lets us map each index back to the cell (i, j).
 
-        let mut is = vec![0; size];
-        let mut js = vec![0; size];
+        let layout = DiagonalMajorLayout::new(height, width);
 
-        let ph = UnsafeSlice::new(&mut h);
-        let pe = UnsafeSlice::new(&mut e);
-        let pf = UnsafeSlice::new(&mut f);
-        let pp = UnsafeSlice::new(&mut p);
-        let pis = UnsafeSlice::new(&mut is);
-        let pjs = UnsafeSlice::new(&mut js);
+        // Initialize the borders (the leftmost column and the topmost row)
+
+        for i in 0..height {
+            let here = layout.index(i, 0);
+            (h[here], e[here], f[here]) = self.mode.border(&self.scoring, i, 0);
+        }
+        for j in 1..width {
+            let here = layout.index(0, j);
+            (h[here], e[here], f[here]) = self.mode.border(&self.scoring, 0, j);
+        }
+
+        let uh = UnsafeSlice::new(h);
+        let ue = UnsafeSlice::new(e);
+        let uf = UnsafeSlice::new(f);
+        let up = UnsafeSlice::new(p);
+
+        let gap_open = S::saturating_from(self.scoring.gap_open as i32);
+        let gap_extend = S::saturating_from(self.scoring.gap_extend as i32);
 
         // Perform scoring stage (dynamic programming-style)
         // We iterate over the diagonals and parallelize over
@@ -62,103 +79,112 @@ impl Engine for OptimizedDiagonalEngine {
         //     \ k = 0 / \     k = 1     / \         k = 2
         //
 
-        let mut previous_previous_size: usize = 1;
-        let mut previous_size: usize = 2;
-        let mut steps_since_in_bottom_part: usize = 0;
-        let mut offset: usize = 3; // We skip the first two diagonals, see below
-
         // We start at 2 since the first interesting (non-border)
         // diagonal starts at i = 2 (going rightwards upwards).
         for k in 2..=(n + m) {
             // The lower and upper bounds for the diagonal('s j index)
             // Derived from rearranging the equations
-            // `k - j = i < height` and `j < width` (our base range is `1..k`).
-            // The outer range represent the entire j-range of the diagonal
-            // whereas the inner range excludes the topmost row and the leftmost
-            // column.
-            let outer_lower = (k as isize - height as isize + 1).max(0) as usize;
-            let outer_upper = (k + 1).min(width);
+            // `k - j = i < height` and `j < width` (our base range is `1..k`),
+            // thus excluding the topmost row and the leftmost column.
             let lower = (k as isize - height as isize + 1).max(1) as usize;
             let upper = k.min(width);
-            let inner_size = upper - lower;
-            let outer_size = outer_upper - outer_lower;
-            let lower_padding = lower - outer_lower;
 
-            if outer_lower > 0 {
-                steps_since_in_bottom_part += 1;
-            }
+            // The cells above and to the left lie on the previous diagonal,
+            // the cell above left on the one before (see `diagonal_base`).
+            let here_base = layout.diagonal_base(k);
+            let previous_base = layout.diagonal_base(k - 1);
+            let previous_previous_base = layout.diagonal_base(k - 2);
 
             // Iterate the diagonal in parallel
-            (0..inner_size).into_par_iter().for_each(|l| {
+            (lower..upper).into_par_iter().for_each(|j| {
                 // Compute the 'actual'/'logical' position in the matrix.
                 // We need this to index into the query/database sequence,
                 // although we use our diagonal-major/cache-optimized
                 // indexing scheme for the matrices instead.
-                let j = lower + l;
                 let i = k - j;
 
                 // Compute indices of the neighboring cells.
-                let here = offset + lower_padding + l;
-                let above = here - previous_size + if steps_since_in_bottom_part > 0 { 1 } else { 0 };
-                let left = above - 1;
-                let above_left = left - previous_previous_size + if steps_since_in_bottom_part > 1 { 1 } else { 0 };
+                let here = here_base + j;
+                let above = previous_base + j;
+                let left = previous_base + j - 1;
+                let above_left = previous_previous_base + j - 1;
                 
                 unsafe {
-                    // Write index mappings
-                    pis.write(here, i);
-                    pjs.write(here, j);
-
                     // Compute helper values
-                    pe.write(here, (pe.read(left) - G_EXT).max(ph.read(left) - G_INIT));
-                    pf.write(here, (pf.read(above) - G_EXT).max(ph.read(above) - G_INIT));
+                    let e_extend = ue.read(left).saturating_sub(gap_extend);
+                    let e_open = uh.read(left).saturating_sub(gap_open);
+                    let f_extend = uf.read(above).saturating_sub(gap_extend);
+                    let f_open = uh.read(above).saturating_sub(gap_open);
+
+                    ue.write(here, e_extend.max(e_open));
+                    uf.write(here, f_extend.max(f_open));
 
-                    // Compute value and remember the index the maximum came from
+                    // Compute value and remember the direction the maximum came from
                     // (we need this later for the traceback phase)
                     let (max_origin, max_value) = [
-                        (0,          0),
-                        (above_left, ph.read(above_left) + Self::weight(database[i - 1], query[j - 1])),
-                        (left,       pe.read(here)),
-                        (above,      pf.read(here)),
+                        (Origin::Start,    self.mode.min_score()),
+                        (Origin::Diagonal, uh.read(above_left).saturating_add(S::saturating_from(self.scoring.weight(database[i - 1], query[j - 1]) as i32))),
+                        (Origin::Left,     ue.read(here)),
+                        (Origin::Above,    uf.read(here)),
                     ].into_iter().max_by_key(|&(_, x)| x).unwrap();
                     
-                    ph.write(here, max_value);
-                    pp.write(here, max_origin);
+                    uh.write(here, max_value);
+                    up.write(here, Direction::new(max_origin, e_extend > e_open, f_extend > f_open));
                 }
             });
+        }
 
-            // Store current values as previous
-            previous_previous_size = previous_size;
-            previous_size = outer_size;
+        // The values (and thus the traceback) are only exact if none of them saturated
 
-            // Move offset
-            offset += outer_size;
+        if h.iter().any(|x| x.is_saturated()) {
+            return Ok(None);
         }
 
-        metrics.lock().unwrap().record_cell_updates(4 * size);
-
         // Perform traceback stage (using the previously computed scoring matrix h)
         // Note that since this returns the last maximum, we may get a different
         // (but equivalently good) solution than the simple diagonal engine if there
         // is more than one maximum.
 
-        let mut i = (0..size).max_by_key(|&i| h[i]).unwrap();
-        let mut database_indices = Vec::new();
-        let mut query_indices = Vec::new();
+        let end = match self.mode.free_end_gaps() {
+            Some(gaps) => gaps.end(n, m, |i, j| h[layout.index(i, j)]),
+            None => {
+                let end = (0..size).max_by_key(|&i| h[i]).unwrap();
+                layout.cell(end)
+            },
+        };
+        Ok(Some(traceback(database, query, self.mode, end, |i, j| layout.index(i, j), |i| TracebackCell {
+            score: h[i].into(),
+            direction: p[i],
+        })))
+    }
+}
 
-        while i > 0 && h[i] > 0 {
-            database_indices.push(is[i] - 1);
-            query_indices.push(js[i] - 1);
-            i = p[i];
-        }
+impl Engine for OptimizedDiagonalEngine {
+    fn name(&self) -> String {
+        "Optimized Diagonal (CPU)".to_owned()
+    }
+
+    fn align<'a>(&self, database: &'a Sequence, query: &'a Sequence, metrics: &Arc<Mutex<Metrics>>) -> Result<AlignedPair<'a>, Error> {
+        // Start with 8-bit scores and widen them if the alignment saturates
+        let aligned = self.align_with::<i8>(database, query).transpose()
+            .or_else(|| self.align_with::<i16>(database, query).transpose())
+            .or_else(|| self.align_with::<i32>(database, query).transpose())
+            .unwrap_or(Err(Error::ScoreOverflow))?;
+
+        metrics.lock().unwrap().record_cell_updates(4 * (database.len() + 1) * (query.len() + 1));
+        metrics.lock().unwrap().record_sequence_pair();
+
+        Ok(aligned)
+    }
 
-        database_indices.reverse();
-        query_indices.reverse();
+    fn score(&self, database: &Sequence, query: &Sequence, metrics: &Arc<Mutex<Metrics>>) -> Result<AlignmentScore, Error> {
+        // Since we only keep the last diagonals, these are
+        // already layed out contiguously (see the diagonal engine)
+        let score = score_diagonals(&self.scoring, self.mode, database, query)?;
 
+        metrics.lock().unwrap().record_cell_updates(4 * (database.len() + 1) * (query.len() + 1));
         metrics.lock().unwrap().record_sequence_pair();
 
-        AlignedPair::new(
-            AlignedSequence::new(database, database_indices),
-            AlignedSequence::new(query, query_indices),
-        )
+        Ok(score)
     }
 }
@@ -0,0 +1,24 @@
+// The type of the values in the matrices, selected by defining SCORE_BITS
+// (8, 16 or 32) when building the program. All arithmetic on these values
+// saturates (using add_sat and sub_sat), thus a value that does not fit
+// into the type shows up at its bounds (see the Score trait on the host).
+#if SCORE_BITS == 8
+typedef char score_t;
+#define SCORE_MIN SCHAR_MIN
+#define SCORE_MAX SCHAR_MAX
+#elif SCORE_BITS == 16
+typedef short score_t;
+#define SCORE_MIN SHRT_MIN
+#define SCORE_MAX SHRT_MAX
+#else
+typedef int score_t;
+#define SCORE_MIN INT_MIN
+#define SCORE_MAX INT_MAX
+#endif
+
+// A value that is small enough to never be the maximum, but
+// large enough to not overflow when subtracting gap penalties.
+#define NEGATIVE_INFINITY (SCORE_MIN / 2)
+
+// Whether a value of h may not be exact anymore.
+#define IS_SATURATED(x) ((x) >= SCORE_MAX || (x) <= NEGATIVE_INFINITY)
@@ -0,0 +1,78 @@
+use std::{fmt::Debug, ops::{BitAnd, BitOr, Not}};
+
+use super::ScoringScheme;
+
+/// An integer type for the values of the matrices. All arithmetic
+/// saturates (rather than wrapping around), thus a value that does not
+/// fit into the type shows up at its bounds. The engines start with
+/// 8-bit scores and recompute the alignment with 16-bit and then 32-bit
+/// scores if any value of h saturates (see `is_saturated`).
+pub trait Score: Copy + Ord + Default + Debug + Send + Sync + Into<i32>
+    + BitAnd<Output = Self> + BitOr<Output = Self> + Not<Output = Self> + 'static {
+    /// The smallest value.
+    const MIN: Self;
+    /// The largest value.
+    const MAX: Self;
+    /// Zero.
+    const ZERO: Self;
+    /// A value that is small enough to never be the maximum, but
+    /// large enough to not overflow when subtracting gap penalties.
+    const NEGATIVE_INFINITY: Self;
+    /// The number of bits (e.g. to select the type in the OpenCL kernels).
+    const BITS: i32;
+
+    /// Converts the given value, saturating at the bounds of the type.
+    fn saturating_from(x: i32) -> Self;
+
+    /// Adds the values, saturating at the bounds of the type.
+    fn saturating_add(self, other: Self) -> Self;
+
+    /// Subtracts the values, saturating at the bounds of the type.
+    fn saturating_sub(self, other: Self) -> Self;
+
+    /// Whether the value (of h) may not be exact anymore. Since h never
+    /// drops below negative infinity (unless a lower value saturated)
+    /// and only grows by the weights, all values are exact as long as
+    /// none of them reaches the maximum or negative infinity.
+    fn is_saturated(self) -> bool {
+        self >= Self::MAX || self <= Self::NEGATIVE_INFINITY
+    }
+
+    /// Whether the weights and gap penalties of the scoring scheme are small
+    /// enough for this type, i.e. subtracting a penalty from a value above
+    /// negative infinity never saturates.
+    fn fits(scoring: &ScoringScheme) -> bool {
+        let limit = -Self::NEGATIVE_INFINITY.into();
+        scoring.matrix.scores().iter()
+            .chain([&scoring.gap_open, &scoring.gap_extend])
+            .all(|&x| (x as i32).abs() < limit)
+    }
+}
+
+macro_rules! impl_score {
+    ($type:ty) => {
+        impl Score for $type {
+            const MIN: Self = <$type>::MIN;
+            const MAX: Self = <$type>::MAX;
+            const ZERO: Self = 0;
+            const NEGATIVE_INFINITY: Self = <$type>::MIN / 2;
+            const BITS: i32 = <$type>::BITS as i32;
+
+            fn saturating_from(x: i32) -> Self {
+                x.clamp(<$type>::MIN as i32, <$type>::MAX as i32) as $type
+            }
+
+            fn saturating_add(self, other: Self) -> Self {
+                <$type>::saturating_add(self, other)
+            }
+
+            fn saturating_sub(self, other: Self) -> Self {
+                <$type>::saturating_sub(self, other)
+            }
+        }
+    };
+}
+
+impl_score!(i8);
+impl_score!(i16);
+impl_score!(i32);
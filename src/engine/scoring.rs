@@ -0,0 +1,33 @@
+use super::SubstitutionMatrix;
+
+/// The parameters for scoring alignments with the
+/// Smith-Waterman algorithm (using affine gap penalties).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScoringScheme {
+    /// The penalty for opening a gap (i.e. the cost of its first residue).
+    pub gap_open: i16,
+    /// The penalty for extending a gap by one residue.
+    pub gap_extend: i16,
+    /// The scores for aligning pairs of residues.
+    pub matrix: SubstitutionMatrix,
+}
+
+impl ScoringScheme {
+    pub fn new(gap_open: i16, gap_extend: i16, matrix: SubstitutionMatrix) -> Self {
+        Self { gap_open, gap_extend, matrix }
+    }
+
+    /// The score for aligning the given two (encoded) residues.
+    pub fn weight(&self, d: u8, q: u8) -> i16 {
+        self.matrix.score(d, q)
+    }
+
+    /// The (negative) score of a gap with the given length.
+    pub fn gap(&self, length: usize) -> i32 {
+        if length == 0 {
+            0
+        } else {
+            -(self.gap_open as i32 + (length as i32 - 1) * self.gap_extend as i32)
+        }
+    }
+}
@@ -0,0 +1,546 @@
+use std::{fmt, marker::PhantomData, str::FromStr};
+
+use super::Score;
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+/// The SIMD instruction set used by the vectorized engines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstructionSet {
+    /// Plain Rust code operating on arrays (available everywhere).
+    Scalar,
+    /// 128-bit vectors with 16 lanes of 8 bits or 8 lanes of 16 bits.
+    Sse2,
+    /// 256-bit vectors with 32 lanes of 8 bits or 16 lanes of 16 bits.
+    Avx2,
+}
+
+impl InstructionSet {
+    /// The names of the instruction sets accepted by `FromStr`.
+    pub const NAMES: [&'static str; 3] = ["scalar", "sse2", "avx2"];
+
+    /// Finds the widest instruction set supported by this CPU.
+    pub fn detect() -> Self {
+        [InstructionSet::Avx2, InstructionSet::Sse2]
+            .into_iter()
+            .find(|set| set.is_available())
+            .unwrap_or(InstructionSet::Scalar)
+    }
+
+    /// The number of lanes per vector for the given score type.
+    pub fn lanes<S: SimdScore>(self) -> usize {
+        match self {
+            InstructionSet::Scalar => Scalar::<S>::LANES,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            InstructionSet::Sse2 => S::Sse2::LANES,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            InstructionSet::Avx2 => S::Avx2::LANES,
+            #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+            _ => unreachable!(),
+        }
+    }
+
+    /// Whether this CPU supports the instruction set.
+    pub fn is_available(self) -> bool {
+        match self {
+            InstructionSet::Scalar => true,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            InstructionSet::Sse2 => is_x86_feature_detected!("sse2"),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            InstructionSet::Avx2 => is_x86_feature_detected!("avx2"),
+            #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for InstructionSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "scalar" => Ok(InstructionSet::Scalar),
+            "sse2" => Ok(InstructionSet::Sse2),
+            "avx2" => Ok(InstructionSet::Avx2),
+            _ => Err(format!(
+                "Unknown instruction set '{}' (supported are: {})",
+                s, InstructionSet::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for InstructionSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstructionSet::Scalar => write!(f, "scalar"),
+            InstructionSet::Sse2 => write!(f, "SSE2"),
+            InstructionSet::Avx2 => write!(f, "AVX2"),
+        }
+    }
+}
+
+/// A vector of lanes with saturating arithmetic. Comparisons return
+/// masks, i.e. lanes with all bits set (true) or none (false).
+///
+/// The methods are unsafe since the caller has to make sure that the
+/// instruction set is available (and that pointers are valid for
+/// `LANES` elements). To make the compiler actually emit the
+/// instructions, generic code using these vectors should be inlined
+/// into a function with the corresponding `#[target_feature]`.
+pub trait Vector: Copy {
+    /// The type of the lanes.
+    type Element: Score;
+
+    /// The number of lanes.
+    const LANES: usize;
+
+    /// Creates a vector with all lanes set to x.
+    unsafe fn splat(x: Self::Element) -> Self;
+
+    /// Loads a vector from the given (unaligned) pointer.
+    unsafe fn load(ptr: *const Self::Element) -> Self;
+
+    /// Stores the vector at the given (unaligned) pointer.
+    unsafe fn store(self, ptr: *mut Self::Element);
+
+    /// Adds the lanes, saturating at the bounds of the element type.
+    unsafe fn adds(self, other: Self) -> Self;
+
+    /// Subtracts the lanes, saturating at the bounds of the element type.
+    unsafe fn subs(self, other: Self) -> Self;
+
+    /// The lane-wise maximum.
+    unsafe fn max(self, other: Self) -> Self;
+
+    /// The lane-wise minimum.
+    unsafe fn min(self, other: Self) -> Self;
+
+    /// The mask of lanes that are equal.
+    unsafe fn eq(self, other: Self) -> Self;
+
+    /// The mask of lanes that are greater than the other's.
+    unsafe fn gt(self, other: Self) -> Self;
+
+    /// The bitwise and.
+    unsafe fn and(self, other: Self) -> Self;
+
+    /// The bitwise and of the negation of self with other.
+    unsafe fn andnot(self, other: Self) -> Self;
+
+    /// The bitwise or.
+    unsafe fn or(self, other: Self) -> Self;
+
+    /// Moves every lane up by one (discarding the last one)
+    /// and inserts x into the first lane.
+    unsafe fn shift_in(self, x: Self::Element) -> Self;
+
+    /// Whether any lane is greater than the other's.
+    unsafe fn any_gt(self, other: Self) -> bool;
+
+    /// The maximum of all lanes.
+    unsafe fn horizontal_max(self) -> Self::Element;
+
+    /// The minimum of all lanes.
+    unsafe fn horizontal_min(self) -> Self::Element;
+}
+
+/// The score types that the instruction sets provide vectors for.
+/// Since neither SSE2 nor AVX2 have saturating arithmetic for 32
+/// bits, 32-bit scores always use the scalar vectors.
+pub trait SimdScore: Score {
+    /// The vector with SSE2 instructions.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    type Sse2: Vector<Element = Self>;
+    /// The vector with AVX2 instructions.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    type Avx2: Vector<Element = Self>;
+}
+
+impl SimdScore for i8 {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    type Sse2 = Sse2<i8>;
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    type Avx2 = Avx2<i8>;
+}
+
+impl SimdScore for i16 {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    type Sse2 = Sse2<i16>;
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    type Avx2 = Avx2<i16>;
+}
+
+impl SimdScore for i32 {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    type Sse2 = Scalar<i32>;
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    type Avx2 = Scalar<i32>;
+}
+
+/// The fallback vector, implemented with plain arrays. The
+/// compiler may still auto-vectorize some of the loops.
+#[derive(Clone, Copy)]
+pub struct Scalar<S>([S; 8]);
+
+impl<S: Score> Scalar<S> {
+    #[inline(always)]
+    fn zip(self, other: Self, f: impl Fn(S, S) -> S) -> Self {
+        Self(std::array::from_fn(|k| f(self.0[k], other.0[k])))
+    }
+
+    #[inline(always)]
+    fn mask(x: bool) -> S {
+        if x { !S::ZERO } else { S::ZERO }
+    }
+}
+
+impl<S: Score> Vector for Scalar<S> {
+    type Element = S;
+
+    const LANES: usize = 8;
+
+    #[inline(always)]
+    unsafe fn splat(x: S) -> Self { Self([x; 8]) }
+
+    #[inline(always)]
+    unsafe fn load(ptr: *const S) -> Self { Self(ptr.cast::<[S; 8]>().read_unaligned()) }
+
+    #[inline(always)]
+    unsafe fn store(self, ptr: *mut S) { ptr.cast::<[S; 8]>().write_unaligned(self.0) }
+
+    #[inline(always)]
+    unsafe fn adds(self, other: Self) -> Self { self.zip(other, S::saturating_add) }
+
+    #[inline(always)]
+    unsafe fn subs(self, other: Self) -> Self { self.zip(other, S::saturating_sub) }
+
+    #[inline(always)]
+    unsafe fn max(self, other: Self) -> Self { self.zip(other, Ord::max) }
+
+    #[inline(always)]
+    unsafe fn min(self, other: Self) -> Self { self.zip(other, Ord::min) }
+
+    #[inline(always)]
+    unsafe fn eq(self, other: Self) -> Self { self.zip(other, |a, b| Self::mask(a == b)) }
+
+    #[inline(always)]
+    unsafe fn gt(self, other: Self) -> Self { self.zip(other, |a, b| Self::mask(a > b)) }
+
+    #[inline(always)]
+    unsafe fn and(self, other: Self) -> Self { self.zip(other, |a, b| a & b) }
+
+    #[inline(always)]
+    unsafe fn andnot(self, other: Self) -> Self { self.zip(other, |a, b| !a & b) }
+
+    #[inline(always)]
+    unsafe fn or(self, other: Self) -> Self { self.zip(other, |a, b| a | b) }
+
+    #[inline(always)]
+    unsafe fn shift_in(self, x: S) -> Self { Self(std::array::from_fn(|k| if k == 0 { x } else { self.0[k - 1] })) }
+
+    #[inline(always)]
+    unsafe fn any_gt(self, other: Self) -> bool { self.0.iter().zip(other.0).any(|(&a, b)| a > b) }
+
+    #[inline(always)]
+    unsafe fn horizontal_max(self) -> S { self.0.into_iter().max().unwrap() }
+
+    #[inline(always)]
+    unsafe fn horizontal_min(self) -> S { self.0.into_iter().min().unwrap() }
+}
+
+/// A vector using SSE2 instructions, with lanes of the given score type.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[derive(Clone, Copy)]
+pub struct Sse2<S>(__m128i, PhantomData<S>);
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+impl<S> Sse2<S> {
+    #[inline(always)]
+    fn new(v: __m128i) -> Self { Self(v, PhantomData) }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+impl Vector for Sse2<i8> {
+    type Element = i8;
+
+    const LANES: usize = 16;
+
+    #[inline(always)]
+    unsafe fn splat(x: i8) -> Self { Self::new(_mm_set1_epi8(x)) }
+
+    #[inline(always)]
+    unsafe fn load(ptr: *const i8) -> Self { Self::new(_mm_loadu_si128(ptr.cast())) }
+
+    #[inline(always)]
+    unsafe fn store(self, ptr: *mut i8) { _mm_storeu_si128(ptr.cast(), self.0) }
+
+    #[inline(always)]
+    unsafe fn adds(self, other: Self) -> Self { Self::new(_mm_adds_epi8(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn subs(self, other: Self) -> Self { Self::new(_mm_subs_epi8(self.0, other.0)) }
+
+    // SSE2 only has the maximum and minimum of unsigned bytes
+    // (the signed ones came with SSE4.1), thus we blend by mask.
+
+    #[inline(always)]
+    unsafe fn max(self, other: Self) -> Self { other.gt(self).and(other).or(other.gt(self).andnot(self)) }
+
+    #[inline(always)]
+    unsafe fn min(self, other: Self) -> Self { self.gt(other).and(other).or(self.gt(other).andnot(self)) }
+
+    #[inline(always)]
+    unsafe fn eq(self, other: Self) -> Self { Self::new(_mm_cmpeq_epi8(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn gt(self, other: Self) -> Self { Self::new(_mm_cmpgt_epi8(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn and(self, other: Self) -> Self { Self::new(_mm_and_si128(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn andnot(self, other: Self) -> Self { Self::new(_mm_andnot_si128(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn or(self, other: Self) -> Self { Self::new(_mm_or_si128(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn shift_in(self, x: i8) -> Self {
+        // There is no insertion of single bytes (before SSE4.1), but
+        // the shift leaves the first lane zeroed, thus we can or it in.
+        Self::new(_mm_or_si128(_mm_slli_si128::<1>(self.0), _mm_cvtsi32_si128(x as u8 as i32)))
+    }
+
+    #[inline(always)]
+    unsafe fn any_gt(self, other: Self) -> bool { _mm_movemask_epi8(_mm_cmpgt_epi8(self.0, other.0)) != 0 }
+
+    #[inline(always)]
+    unsafe fn horizontal_max(self) -> i8 {
+        let mut lanes = [0; 16];
+        self.store(lanes.as_mut_ptr());
+        lanes.into_iter().max().unwrap()
+    }
+
+    #[inline(always)]
+    unsafe fn horizontal_min(self) -> i8 {
+        let mut lanes = [0; 16];
+        self.store(lanes.as_mut_ptr());
+        lanes.into_iter().min().unwrap()
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+impl Vector for Sse2<i16> {
+    type Element = i16;
+
+    const LANES: usize = 8;
+
+    #[inline(always)]
+    unsafe fn splat(x: i16) -> Self { Self::new(_mm_set1_epi16(x)) }
+
+    #[inline(always)]
+    unsafe fn load(ptr: *const i16) -> Self { Self::new(_mm_loadu_si128(ptr.cast())) }
+
+    #[inline(always)]
+    unsafe fn store(self, ptr: *mut i16) { _mm_storeu_si128(ptr.cast(), self.0) }
+
+    #[inline(always)]
+    unsafe fn adds(self, other: Self) -> Self { Self::new(_mm_adds_epi16(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn subs(self, other: Self) -> Self { Self::new(_mm_subs_epi16(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn max(self, other: Self) -> Self { Self::new(_mm_max_epi16(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn min(self, other: Self) -> Self { Self::new(_mm_min_epi16(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn eq(self, other: Self) -> Self { Self::new(_mm_cmpeq_epi16(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn gt(self, other: Self) -> Self { Self::new(_mm_cmpgt_epi16(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn and(self, other: Self) -> Self { Self::new(_mm_and_si128(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn andnot(self, other: Self) -> Self { Self::new(_mm_andnot_si128(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn or(self, other: Self) -> Self { Self::new(_mm_or_si128(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn shift_in(self, x: i16) -> Self {
+        Self::new(_mm_insert_epi16::<0>(_mm_slli_si128::<2>(self.0), x as i32))
+    }
+
+    #[inline(always)]
+    unsafe fn any_gt(self, other: Self) -> bool { _mm_movemask_epi8(_mm_cmpgt_epi16(self.0, other.0)) != 0 }
+
+    #[inline(always)]
+    unsafe fn horizontal_max(self) -> i16 {
+        let mut lanes = [0; 8];
+        self.store(lanes.as_mut_ptr());
+        lanes.into_iter().max().unwrap()
+    }
+
+    #[inline(always)]
+    unsafe fn horizontal_min(self) -> i16 {
+        let mut lanes = [0; 8];
+        self.store(lanes.as_mut_ptr());
+        lanes.into_iter().min().unwrap()
+    }
+}
+
+/// A vector using AVX2 instructions, with lanes of the given score type.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[derive(Clone, Copy)]
+pub struct Avx2<S>(__m256i, PhantomData<S>);
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+impl<S> Avx2<S> {
+    #[inline(always)]
+    fn new(v: __m256i) -> Self { Self(v, PhantomData) }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+impl Vector for Avx2<i8> {
+    type Element = i8;
+
+    const LANES: usize = 32;
+
+    #[inline(always)]
+    unsafe fn splat(x: i8) -> Self { Self::new(_mm256_set1_epi8(x)) }
+
+    #[inline(always)]
+    unsafe fn load(ptr: *const i8) -> Self { Self::new(_mm256_loadu_si256(ptr.cast())) }
+
+    #[inline(always)]
+    unsafe fn store(self, ptr: *mut i8) { _mm256_storeu_si256(ptr.cast(), self.0) }
+
+    #[inline(always)]
+    unsafe fn adds(self, other: Self) -> Self { Self::new(_mm256_adds_epi8(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn subs(self, other: Self) -> Self { Self::new(_mm256_subs_epi8(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn max(self, other: Self) -> Self { Self::new(_mm256_max_epi8(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn min(self, other: Self) -> Self { Self::new(_mm256_min_epi8(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn eq(self, other: Self) -> Self { Self::new(_mm256_cmpeq_epi8(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn gt(self, other: Self) -> Self { Self::new(_mm256_cmpgt_epi8(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn and(self, other: Self) -> Self { Self::new(_mm256_and_si256(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn andnot(self, other: Self) -> Self { Self::new(_mm256_andnot_si256(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn or(self, other: Self) -> Self { Self::new(_mm256_or_si256(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn shift_in(self, x: i8) -> Self {
+        // Carry over the last lane of the lower half like for 16 bits.
+        let carry = _mm256_permute2x128_si256::<0x08>(self.0, self.0);
+        Self::new(_mm256_insert_epi8::<0>(_mm256_alignr_epi8::<15>(self.0, carry), x))
+    }
+
+    #[inline(always)]
+    unsafe fn any_gt(self, other: Self) -> bool { _mm256_movemask_epi8(_mm256_cmpgt_epi8(self.0, other.0)) != 0 }
+
+    #[inline(always)]
+    unsafe fn horizontal_max(self) -> i8 {
+        let mut lanes = [0; 32];
+        self.store(lanes.as_mut_ptr());
+        lanes.into_iter().max().unwrap()
+    }
+
+    #[inline(always)]
+    unsafe fn horizontal_min(self) -> i8 {
+        let mut lanes = [0; 32];
+        self.store(lanes.as_mut_ptr());
+        lanes.into_iter().min().unwrap()
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+impl Vector for Avx2<i16> {
+    type Element = i16;
+
+    const LANES: usize = 16;
+
+    #[inline(always)]
+    unsafe fn splat(x: i16) -> Self { Self::new(_mm256_set1_epi16(x)) }
+
+    #[inline(always)]
+    unsafe fn load(ptr: *const i16) -> Self { Self::new(_mm256_loadu_si256(ptr.cast())) }
+
+    #[inline(always)]
+    unsafe fn store(self, ptr: *mut i16) { _mm256_storeu_si256(ptr.cast(), self.0) }
+
+    #[inline(always)]
+    unsafe fn adds(self, other: Self) -> Self { Self::new(_mm256_adds_epi16(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn subs(self, other: Self) -> Self { Self::new(_mm256_subs_epi16(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn max(self, other: Self) -> Self { Self::new(_mm256_max_epi16(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn min(self, other: Self) -> Self { Self::new(_mm256_min_epi16(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn eq(self, other: Self) -> Self { Self::new(_mm256_cmpeq_epi16(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn gt(self, other: Self) -> Self { Self::new(_mm256_cmpgt_epi16(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn and(self, other: Self) -> Self { Self::new(_mm256_and_si256(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn andnot(self, other: Self) -> Self { Self::new(_mm256_andnot_si256(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn or(self, other: Self) -> Self { Self::new(_mm256_or_si256(self.0, other.0)) }
+
+    #[inline(always)]
+    unsafe fn shift_in(self, x: i16) -> Self {
+        // The byte shift only operates within the two 128-bit halves,
+        // thus we carry the last lane of the lower half over by aligning
+        // with a copy that has the lower half moved into the upper one.
+        let carry = _mm256_permute2x128_si256::<0x08>(self.0, self.0);
+        Self::new(_mm256_insert_epi16::<0>(_mm256_alignr_epi8::<14>(self.0, carry), x))
+    }
+
+    #[inline(always)]
+    unsafe fn any_gt(self, other: Self) -> bool { _mm256_movemask_epi8(_mm256_cmpgt_epi16(self.0, other.0)) != 0 }
+
+    #[inline(always)]
+    unsafe fn horizontal_max(self) -> i16 {
+        let mut lanes = [0; 16];
+        self.store(lanes.as_mut_ptr());
+        lanes.into_iter().max().unwrap()
+    }
+
+    #[inline(always)]
+    unsafe fn horizontal_min(self) -> i16 {
+        let mut lanes = [0; 16];
+        self.store(lanes.as_mut_ptr());
+        lanes.into_iter().min().unwrap()
+    }
+}
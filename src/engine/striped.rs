@@ -0,0 +1,459 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{model::{Sequence, AlignedPair, AlignmentScore}, metrics::Metrics, error::Error, utils::Pool};
+
+use super::{AlignmentMode, Direction, Engine, InstructionSet, Origin, Score, ScoringScheme, SimdScore, TracebackCell, Vector, Scalar, resized, traceback};
+
+/// The flags recorded for each cell that the traceback needs: Where the
+/// value of h came from (the diagonal unless one of the first two flags
+/// is set) and whether e and f extend a gap (rather than opening one).
+const FROM_LEFT: i32 = 1;
+const FROM_ABOVE: i32 = 2;
+const E_EXTENDED: i32 = 4;
+const F_EXTENDED: i32 = 8;
+
+/// An engine that computes alignments using the
+/// Smith-Waterman-Algorithm with Farrar's striped
+/// SIMD approach on the CPU. Rather than parallelizing
+/// over the diagonals, this vectorizes each row of the
+/// matrix, with the query striped across the lanes.
+pub struct StripedEngine {
+    scoring: ScoringScheme,
+    mode: AlignmentMode,
+    instruction_set: InstructionSet,
+    workspaces: Pool,
+}
+
+impl StripedEngine {
+    /// Creates the engine, which fails if the CPU does not support the instruction set.
+    pub fn new(scoring: ScoringScheme, mode: AlignmentMode, instruction_set: InstructionSet) -> Result<Self, Error> {
+        if !instruction_set.is_available() {
+            return Err(Error::UnsupportedInstructionSet(instruction_set));
+        }
+        Ok(Self { scoring, mode, instruction_set, workspaces: Pool::new() })
+    }
+
+    /// Computes the score and the end of the alignment with the
+    /// selected instruction set and the given score type, optionally
+    /// recording the matrices for the traceback. Returns none if
+    /// any value of h saturates.
+    fn striped<S: SimdScore>(&self, database: &Sequence, query: &Sequence, matrices: Option<&mut StripedMatrices<S>>) -> Option<AlignmentScore> {
+        // Every instruction set gets its own copy of the generic implementation,
+        // compiled with the corresponding target feature (otherwise the compiler
+        // would not emit the instructions).
+        unsafe {
+            match self.instruction_set {
+                InstructionSet::Scalar => striped::<S, Scalar<S>>(&self.scoring, self.mode, database, query, matrices),
+                #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+                InstructionSet::Sse2 => striped_sse2::<S>(&self.scoring, self.mode, database, query, matrices),
+                #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+                InstructionSet::Avx2 => striped_avx2::<S>(&self.scoring, self.mode, database, query, matrices),
+                #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    /// Aligns the given two sequences with the smallest score type that does
+    /// not saturate. Unlike `align`, this does not record any metrics, which
+    /// lets the inter-sequence engine trace back its hits without counting
+    /// the cells twice.
+    pub fn align_pair<'a>(&self, database: &'a Sequence, query: &'a Sequence) -> Result<AlignedPair<'a>, Error> {
+        // Start with 8-bit scores and widen them if the alignment saturates
+        self.align_with::<i8>(database, query).transpose()
+            .or_else(|| self.align_with::<i16>(database, query).transpose())
+            .or_else(|| self.align_with::<i32>(database, query).transpose())
+            .unwrap_or(Err(Error::ScoreOverflow))
+    }
+
+    /// Aligns the given two sequences using the given score type,
+    /// or returns none if any value of h saturates.
+    fn align_with<'a, S: SimdScore>(&self, database: &'a Sequence, query: &'a Sequence) -> Result<Option<AlignedPair<'a>>, Error> {
+        let n = database.len();
+        let m = query.len();
+        let layout = StripedLayout::new(m, self.instruction_set.lanes::<S>());
+        let size = (n + 1).checked_mul(layout.stride()).ok_or(Error::MatrixTooLarge { database: n, query: m })?;
+
+        // Perform scoring stage, recording h and the flags for the traceback
+
+        let mut matrices = self.workspaces.take(StripedMatrices::<S>::default);
+        resized(&mut matrices.h, size)?;
+        resized(&mut matrices.flags, size)?;
+        let score = match self.striped(database, query, Some(&mut matrices)) {
+            Some(score) => score,
+            None => return Ok(None),
+        };
+
+        // Perform traceback stage. The flags only tell us where the value came from
+        // in the interior of the matrix, so we need to map the index back to the cell.
+
+        let index = |i, j| layout.index(i, j);
+        Ok(Some(traceback(database, query, self.mode, (score.database_end, score.query_end), index, |c| {
+            let (i, j) = layout.cell(c);
+            let flags: i32 = matrices.flags[c].into();
+            let origin = if i == 0 || j == 0 {
+                Origin::Start
+            } else if flags & FROM_ABOVE != 0 {
+                Origin::Above
+            } else if flags & FROM_LEFT != 0 {
+                Origin::Left
+            } else {
+                Origin::Diagonal
+            };
+            TracebackCell {
+                score: matrices.h[c].into(),
+                direction: Direction::new(origin, flags & E_EXTENDED != 0, flags & F_EXTENDED != 0),
+            }
+        })))
+    }
+}
+
+impl Engine for StripedEngine {
+    fn name(&self) -> String {
+        format!("Striped (CPU, {})", self.instruction_set)
+    }
+
+    fn align<'a>(&self, database: &'a Sequence, query: &'a Sequence, metrics: &Arc<Mutex<Metrics>>) -> Result<AlignedPair<'a>, Error> {
+        let aligned = self.align_pair(database, query)?;
+
+        metrics.lock().unwrap().record_cell_updates(4 * (database.len() + 1) * (query.len() + 1));
+        metrics.lock().unwrap().record_sequence_pair();
+
+        Ok(aligned)
+    }
+
+    fn score(&self, database: &Sequence, query: &Sequence, metrics: &Arc<Mutex<Metrics>>) -> Result<AlignmentScore, Error> {
+        let score = self.striped::<i8>(database, query, None)
+            .or_else(|| self.striped::<i16>(database, query, None))
+            .or_else(|| self.striped::<i32>(database, query, None))
+            .ok_or(Error::ScoreOverflow)?;
+
+        metrics.lock().unwrap().record_cell_updates(4 * (database.len() + 1) * (query.len() + 1));
+        metrics.lock().unwrap().record_sequence_pair();
+
+        Ok(score)
+    }
+}
+
+/// The layout of the query striped across the lanes of a vector:
+/// The query is split into one segment per lane, with vector s
+/// holding the residue s of each segment. Thus, the cells that
+/// depend on each other (from left to right) are in different
+/// vectors, except for the transitions between the segments.
+///
+/// For example, with 4 lanes and 10 residues (and thus a segment
+/// length of 3), the columns are layed out as `1 4 7 10 | 2 5 8 - |
+/// 3 6 9 -`, where `-` denotes padding.
+#[derive(Clone, Copy)]
+struct StripedLayout {
+    lanes: usize,
+    segment_length: usize,
+}
+
+impl StripedLayout {
+    fn new(m: usize, lanes: usize) -> Self {
+        Self { lanes, segment_length: m.div_ceil(lanes).max(1) }
+    }
+
+    /// The number of cells per row (including the padding).
+    fn width(self) -> usize {
+        self.segment_length * self.lanes
+    }
+
+    /// The number of cells per row in the recorded matrices,
+    /// which additionally store the leftmost column first.
+    fn stride(self) -> usize {
+        self.width() + 1
+    }
+
+    /// The offset of column j > 0 within a row.
+    fn offset(self, j: usize) -> usize {
+        let l = j - 1;
+        (l % self.segment_length) * self.lanes + l / self.segment_length
+    }
+
+    /// The column at the given offset within a row.
+    fn column(self, offset: usize) -> usize {
+        (offset % self.lanes) * self.segment_length + offset / self.lanes + 1
+    }
+
+    /// The index of the cell (i, j) in the recorded matrices.
+    fn index(self, i: usize, j: usize) -> usize {
+        i * self.stride() + if j == 0 { 0 } else { 1 + self.offset(j) }
+    }
+
+    /// The cell (i, j) at the given index in the recorded matrices.
+    fn cell(self, index: usize) -> (usize, usize) {
+        let i = index / self.stride();
+        let j = match index % self.stride() {
+            0 => 0,
+            offset => self.column(offset - 1),
+        };
+        (i, j)
+    }
+}
+
+/// The values of h and the flags of every cell, as recorded for the
+/// traceback. Each row holds the leftmost column followed by the
+/// remaining columns in the striped layout. Like the workspaces of the
+/// other engines, these are reused across alignments (see `Workspace`).
+struct StripedMatrices<S> {
+    h: Vec<S>,
+    flags: Vec<S>,
+}
+
+impl<S> Default for StripedMatrices<S> {
+    fn default() -> Self {
+        Self { h: Vec::new(), flags: Vec::new() }
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse2")]
+unsafe fn striped_sse2<S: SimdScore>(scoring: &ScoringScheme, mode: AlignmentMode, database: &Sequence, query: &Sequence, matrices: Option<&mut StripedMatrices<S>>) -> Option<AlignmentScore> {
+    striped::<S, S::Sse2>(scoring, mode, database, query, matrices)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn striped_avx2<S: SimdScore>(scoring: &ScoringScheme, mode: AlignmentMode, database: &Sequence, query: &Sequence, matrices: Option<&mut StripedMatrices<S>>) -> Option<AlignmentScore> {
+    striped::<S, S::Avx2>(scoring, mode, database, query, matrices)
+}
+
+/// Computes the score of the alignment and where it ends row by row,
+/// vectorizing each row using Farrar's striped layout of the query.
+///
+/// Since the cells of a vector are in different segments, the values
+/// of h and f (from the row above) are available right away, whereas
+/// the gaps e (from the left) may cross from one segment into the next.
+/// We thus first compute the row as if no gap crosses the segments and
+/// then propagate these gaps in the 'lazy e' loop, which usually stops
+/// after a few vectors (once no lane improves anymore).
+///
+/// Since all arithmetic saturates, we return none as soon as a value
+/// of h saturates (see `Score::is_saturated`).
+///
+/// The caller has to make sure that the instruction set is available.
+#[inline(always)]
+unsafe fn striped<S: Score, V: Vector<Element = S>>(
+    scoring: &ScoringScheme,
+    mode: AlignmentMode,
+    database: &Sequence,
+    query: &Sequence,
+    mut matrices: Option<&mut StripedMatrices<S>>,
+) -> Option<AlignmentScore> {
+    if !S::fits(scoring) {
+        return None;
+    }
+
+    let n = database.len();
+    let m = query.len();
+    let layout = StripedLayout::new(m, V::LANES);
+    let width = layout.width();
+    let lanes = V::LANES;
+
+    let open = S::saturating_from(scoring.gap_open as i32);
+    let extend = S::saturating_from(scoring.gap_extend as i32);
+    let gap_open = V::splat(open);
+    let gap_extend = V::splat(extend);
+    let min_score = V::splat(mode.min_score());
+
+    // Create the query profile, i.e. the weights of each residue (of the
+    // alphabet) against the striped query. The padding cells get negative
+    // infinity, thus they never affect the actual cells. Additionally, we
+    // use masks to exclude the padding from the maxima and minima.
+
+    let alphabet_size = scoring.matrix.size();
+    let profile: Vec<_> = (0..alphabet_size)
+        .flat_map(|r| (0..width).map(move |offset| match layout.column(offset) {
+            j if j <= m => S::saturating_from(scoring.weight(r as u8, query[j - 1]) as i32),
+            _ => S::NEGATIVE_INFINITY,
+        }))
+        .collect();
+    let padding_mask: Vec<_> = (0..width)
+        .map(|offset| if layout.column(offset) <= m { S::MAX } else { S::MIN })
+        .collect();
+    let padding_floor: Vec<_> = padding_mask.iter().map(|&x| !x).collect();
+
+    // Compute the borders (the topmost row and the leftmost column) of h.
+    // The borders of e and f are always negative infinity.
+
+    let border_row: Vec<S> = (0..=m).map(|j| mode.border(scoring, 0, j).0).collect();
+    let border_column: Vec<S> = (0..=n).map(|i| mode.border(scoring, i, 0).0).collect();
+
+    if border_row.iter().chain(&border_column).any(|x| x.is_saturated()) {
+        return None;
+    }
+
+    // Create the last and the current row of h and the current rows of e
+    // and f (in the striped layout). After each row, we update f in place
+    // to hold the values of the next row.
+
+    let mut h_previous: Vec<_> = (0..width)
+        .map(|offset| border_row.get(layout.column(offset)).copied().unwrap_or(S::NEGATIVE_INFINITY))
+        .collect();
+    let mut h_current = vec![S::ZERO; width];
+    let mut e = vec![S::ZERO; width];
+    let mut f: Vec<_> = h_previous.iter()
+        .map(|&h| S::NEGATIVE_INFINITY.saturating_sub(extend).max(h.saturating_sub(open)))
+        .collect();
+
+    if let Some(matrices) = matrices.as_deref_mut() {
+        matrices.h[0] = border_row[0];
+        matrices.h[1..layout.stride()].copy_from_slice(&h_previous);
+        matrices.flags[..layout.stride()].fill(S::ZERO);
+    }
+
+    // To find the end of the alignment, we track the maximum
+    // of each row and the last column (as in the other engines).
+    // Since only the rows that reach the maximum so far can be
+    // the end, we only look up the column for these rows.
+
+    let mut row_maxima: Vec<_> = border_column.iter().map(|&x| (x, 0)).collect();
+    let mut last_column = border_column.clone();
+
+    row_maxima[0] = (0..=m).map(|j| (border_row[j], j)).max_by_key(|&(x, _)| x).unwrap();
+    last_column[0] = border_row[m];
+    let mut best = row_maxima[0].0;
+
+    // Perform scoring stage (dynamic programming-style)
+
+    for i in 1..=n {
+        let weights = profile.as_ptr().add(database[i - 1] as usize * width);
+        let h_previous_ptr = h_previous.as_mut_ptr();
+        let h_current_ptr = h_current.as_mut_ptr();
+        let e_ptr = e.as_mut_ptr();
+        let f_ptr = f.as_mut_ptr();
+
+        // Compute the row, assuming that no gap crosses the segments (except
+        // for the first one, which opens the gap from the leftmost column).
+        // The value above left of the first vector is the last vector of
+        // the row above, shifted by one lane.
+
+        let e_border = S::NEGATIVE_INFINITY.saturating_sub(extend).max(border_column[i].saturating_sub(open));
+        let mut e_here = V::splat(S::NEGATIVE_INFINITY).shift_in(e_border);
+        let mut h_above_left = V::load(h_previous_ptr.add((layout.segment_length - 1) * lanes)).shift_in(border_column[i - 1]);
+
+        for s in 0..layout.segment_length {
+            let offset = s * lanes;
+            let h_here = h_above_left
+                .adds(V::load(weights.add(offset)))
+                .max(e_here)
+                .max(V::load(f_ptr.add(offset)))
+                .max(min_score);
+
+            h_here.store(h_current_ptr.add(offset));
+            e_here.store(e_ptr.add(offset));
+
+            e_here = e_here.subs(gap_extend).max(h_here.subs(gap_open));
+            h_above_left = V::load(h_previous_ptr.add(offset));
+        }
+
+        // Propagate the gaps across the segments ('lazy e' loop), moving the
+        // values from the end of each segment into the start of the next one.
+
+        let mut e_here = e_here.shift_in(S::NEGATIVE_INFINITY);
+        let mut s = 0;
+
+        while e_here.any_gt(V::load(e_ptr.add(s * lanes))) {
+            let offset = s * lanes;
+            let e_here_max = e_here.max(V::load(e_ptr.add(offset)));
+            let h_here = V::load(h_current_ptr.add(offset)).max(e_here_max);
+
+            h_here.store(h_current_ptr.add(offset));
+            e_here_max.store(e_ptr.add(offset));
+
+            e_here = e_here_max.subs(gap_extend).max(h_here.subs(gap_open));
+            s += 1;
+            if s == layout.segment_length {
+                s = 0;
+                e_here = e_here.shift_in(S::NEGATIVE_INFINITY);
+            }
+        }
+
+        // Compute f for the next row and the maximum of the row, recording
+        // the flags for the traceback (if needed). Since h may have changed
+        // in the lazy loop, we derive the flags from the final values.
+
+        let mut row_maximum = V::splat(S::MIN);
+        let mut row_minimum = V::splat(S::MAX);
+        let mut h_left = V::load(h_current_ptr.add((layout.segment_length - 1) * lanes)).shift_in(border_column[i]);
+
+        for s in 0..layout.segment_length {
+            let offset = s * lanes;
+            let h_here = V::load(h_current_ptr.add(offset));
+            let e_here = V::load(e_ptr.add(offset));
+            let f_here = V::load(f_ptr.add(offset));
+
+            if let Some(matrices) = matrices.as_deref_mut() {
+                // Ties are resolved like in the other engines, i.e.
+                // preferring f over e over the diagonal.
+                let from_above = h_here.eq(f_here);
+                let from_left = from_above.andnot(h_here.eq(e_here));
+                let e_extended = e_here.gt(h_left.subs(gap_open));
+                let f_extended = f_here.gt(V::load(h_previous_ptr.add(offset)).subs(gap_open));
+                let flag = |x| V::splat(S::saturating_from(x));
+                let flags = from_above.and(flag(FROM_ABOVE))
+                    .or(from_left.and(flag(FROM_LEFT)))
+                    .or(e_extended.and(flag(E_EXTENDED)))
+                    .or(f_extended.and(flag(F_EXTENDED)));
+
+                let here = i * layout.stride() + 1 + offset;
+                h_here.store(matrices.h.as_mut_ptr().add(here));
+                flags.store(matrices.flags.as_mut_ptr().add(here));
+            }
+
+            f_here.subs(gap_extend).max(h_here.subs(gap_open)).store(f_ptr.add(offset));
+            row_maximum = row_maximum.max(h_here.min(V::load(padding_mask.as_ptr().add(offset))));
+            row_minimum = row_minimum.min(h_here.max(V::load(padding_floor.as_ptr().add(offset))));
+            h_left = h_here;
+        }
+
+        if let Some(matrices) = matrices.as_deref_mut() {
+            matrices.h[i * layout.stride()] = border_column[i];
+            matrices.flags[i * layout.stride()] = S::ZERO;
+        }
+
+        let row_maximum = row_maximum.horizontal_max().max(border_column[i]);
+        let row_minimum = row_minimum.horizontal_min().min(border_column[i]);
+        if row_maximum.is_saturated() || row_minimum.is_saturated() {
+            return None;
+        }
+
+        if row_maximum >= best {
+            best = row_maximum;
+            let j = (1..=m).rev().find(|&j| h_current[layout.offset(j)] == row_maximum).unwrap_or(0);
+            row_maxima[i] = (row_maximum, j);
+        } else {
+            row_maxima[i] = (row_maximum, 0);
+        }
+        if m > 0 {
+            last_column[i] = h_current[layout.offset(m)];
+        }
+
+        std::mem::swap(&mut h_previous, &mut h_current);
+    }
+
+    let last_row: Vec<_> = (0..=m)
+        .map(|j| if j == 0 { border_column[n] } else { h_previous[layout.offset(j)] })
+        .collect();
+
+    Some(mode.find_end(&row_maxima, &last_row, &last_column))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::{InstructionSet, testing::assert_matches_naive};
+
+    use super::StripedEngine;
+
+    #[test]
+    fn matches_naive() {
+        for name in InstructionSet::NAMES {
+            let instruction_set: InstructionSet = name.parse().unwrap();
+            if instruction_set.is_available() {
+                assert_matches_naive(|scoring, mode| StripedEngine::new(scoring, mode, instruction_set).unwrap());
+            }
+        }
+    }
+}
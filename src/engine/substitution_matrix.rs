@@ -0,0 +1,218 @@
+use std::fmt;
+
+use crate::alphabet::Alphabet;
+
+/// The residues that unknown residues are mapped to (in
+/// order of preference), if present in the matrix.
+const WILDCARDS: [u8; 3] = [b'X', b'N', b'*'];
+
+/// The nucleotides (including the IUPAC ambiguity codes), which
+/// are the only residues of a matrix for nucleotides.
+const NUCLEOTIDES: &[u8] = b"ACGTURYSWKMBDHVN";
+
+/// The substitution matrices that ship with the program.
+const BUILTINS: [(&str, &str); 5] = [
+    ("BLOSUM62", include_str!("matrices/BLOSUM62")),
+    ("PAM40", include_str!("matrices/PAM40")),
+    ("PAM120", include_str!("matrices/PAM120")),
+    ("PAM200", include_str!("matrices/PAM200")),
+    ("PAM250", include_str!("matrices/PAM250")),
+];
+
+/// A matrix of scores for aligning pairs of residues,
+/// e.g. BLOSUM62 or PAM250. Rows and columns are indexed
+/// by the (encoded) residues of an alphabet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubstitutionMatrix {
+    alphabet: Alphabet,
+    /// The scores in row-major order.
+    scores: Vec<i16>,
+}
+
+/// An error that occurred while parsing a substitution matrix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseMatrixError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl SubstitutionMatrix {
+    /// Creates a matrix that scores equal residues with the given
+    /// match score and different residues with the mismatch score.
+    pub fn uniform(alphabet: Alphabet, match_score: i16, mismatch_score: i16) -> Self {
+        let size = alphabet.len();
+        let scores = (0..size)
+            .flat_map(|d| (0..size).map(move |q| if d == q { match_score } else { mismatch_score }))
+            .collect();
+        Self { alphabet, scores }
+    }
+
+    /// Parses a matrix in the NCBI/EMBOSS format, i.e. a header line
+    /// with the residues followed by one line per row, each starting
+    /// with the residue. Lines starting with '#' are comments.
+    /// Residues of the alphabet that are missing from the matrix are
+    /// scored as the first wildcard (X, N or *) that the matrix contains.
+    /// The matrix has to be for the same kind of residues as the alphabet,
+    /// i.e. nucleotides or amino acids.
+    pub fn parse(s: &str, alphabet: Alphabet) -> Result<Self, ParseMatrixError> {
+        let mut lines = s.lines()
+            .enumerate()
+            .map(|(i, l)| (i + 1, l.trim()))
+            .filter(|(_, l)| !l.is_empty() && !l.starts_with('#'));
+
+        let error = |line: usize, message: String| ParseMatrixError { line, message };
+        // The header or rows that are missing are reported at the end of the input
+        let end = s.lines().count() + 1;
+        let parse_residue = |line: usize, token: &str| match token.as_bytes() {
+            &[r] if r.is_ascii_graphic() => Ok(r.to_ascii_uppercase()),
+            _ => Err(error(line, format!("Invalid residue '{}'", token))),
+        };
+
+        // Parse the header
+        let (header_line, header) = lines.next().ok_or_else(|| error(end, "Missing header".to_owned()))?;
+        let residues = header.split_whitespace()
+            .map(|t| parse_residue(header_line, t))
+            .collect::<Result<Vec<_>, _>>()?;
+        let size = residues.len();
+
+        // Reject matrices for the other kind of residues (e.g. a protein matrix
+        // for DNA would silently score the nucleotides as amino acids)
+        let is_nucleotide = residues.iter().all(|r| NUCLEOTIDES.contains(r) || WILDCARDS.contains(r));
+        if is_nucleotide != alphabet.is_nucleotide() {
+            let kind = |nucleotide| if nucleotide { "nucleotides" } else { "amino acids" };
+            return Err(error(header_line, format!(
+                "The matrix scores {}, but alphabet {} consists of {}",
+                kind(is_nucleotide), alphabet, kind(alphabet.is_nucleotide())
+            )));
+        }
+
+        // Parse the rows
+        let mut scores = vec![0; size * size];
+        let mut parsed = vec![false; size];
+
+        for (line, row) in lines {
+            let mut tokens = row.split_whitespace();
+            let residue = parse_residue(line, tokens.next().unwrap())?;
+            let i = residues.iter()
+                .position(|&r| r == residue)
+                .ok_or_else(|| error(line, format!("Row residue '{}' is not in the header", residue as char)))?;
+            if parsed[i] {
+                return Err(error(line, format!("Duplicate row for '{}'", residue as char)));
+            }
+
+            let row_scores = tokens
+                .map(|t| t.parse::<i16>().map_err(|_| error(line, format!("Invalid score '{}'", t))))
+                .collect::<Result<Vec<_>, _>>()?;
+            if row_scores.len() != size {
+                return Err(error(line, format!("Expected {} scores, but got {}", size, row_scores.len())));
+            }
+
+            scores[(i * size)..((i + 1) * size)].copy_from_slice(&row_scores);
+            parsed[i] = true;
+        }
+
+        if let Some(i) = parsed.iter().position(|&p| !p) {
+            return Err(error(end, format!("Missing row for '{}'", residues[i] as char)));
+        }
+
+        // Map the alphabet's residues to the rows/columns
+        let wildcard = WILDCARDS.iter().find_map(|w| residues.iter().position(|r| r == w));
+        let indices = alphabet.symbols().iter()
+            .map(|s| residues.iter().position(|r| r == s).or(wildcard).ok_or_else(|| error(
+                header_line,
+                format!("Residue '{}' of alphabet {} is missing", *s as char, alphabet),
+            )))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let scores = indices.iter()
+            .flat_map(|&i| indices.iter().map(move |&j| (i, j)))
+            .map(|(i, j)| scores[i * size + j])
+            .collect();
+
+        Ok(Self { alphabet, scores })
+    }
+
+    /// Fetches one of the built-in matrices by (case-insensitive) name.
+    pub fn builtin(name: &str, alphabet: Alphabet) -> Option<Result<Self, ParseMatrixError>> {
+        BUILTINS.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, src)| Self::parse(src, alphabet))
+    }
+
+    /// The names of the built-in matrices.
+    pub fn builtin_names() -> impl Iterator<Item = &'static str> {
+        BUILTINS.iter().map(|&(n, _)| n)
+    }
+
+    /// The number of rows (and columns) of the matrix.
+    pub fn size(&self) -> usize {
+        self.alphabet.len()
+    }
+
+    /// The scores in row-major order.
+    pub fn scores(&self) -> &[i16] {
+        &self.scores
+    }
+
+    /// The score for aligning the given two (encoded) residues.
+    pub fn score(&self, d: u8, q: u8) -> i16 {
+        self.scores[d as usize * self.size() + q as usize]
+    }
+}
+
+impl fmt::Display for ParseMatrixError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at line {})", self.message, self.line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::alphabet::Alphabet;
+
+    use super::SubstitutionMatrix;
+
+    /// Parses the given matrix for DNA, returning the line and message of the error.
+    fn parse_error(s: &str) -> (usize, String) {
+        let error = SubstitutionMatrix::parse(s, Alphabet::Dna).unwrap_err();
+        (error.line, error.message)
+    }
+
+    #[test]
+    fn parses_matrices() {
+        let matrix = SubstitutionMatrix::parse("# A comment\n   A  C  G  T\nA  5 -4 -4 -4\nC -4  5 -4 -4\nG -4 -4  5 -4\nT -4 -4 -4  5\n", Alphabet::Dna).unwrap();
+        assert_eq!(matrix, SubstitutionMatrix::uniform(Alphabet::Dna, 5, -4));
+
+        // The residues missing from the matrix are scored as the wildcard
+        let matrix = SubstitutionMatrix::parse("A C G N\nA 1 0 0 -1\nC 0 1 0 -1\nG 0 0 1 -1\nN -1 -1 -1 -2", Alphabet::Dna).unwrap();
+        let t = Alphabet::Dna.encode_residue(b'T').unwrap();
+        assert_eq!(matrix.score(t, t), -2);
+    }
+
+    #[test]
+    fn reports_misformatted_matrices() {
+        assert_eq!(parse_error(""), (1, "Missing header".to_owned()));
+        assert_eq!(parse_error("# Only a comment\n"), (2, "Missing header".to_owned()));
+        assert_eq!(parse_error("A C G T\nA 1 0 0 x"), (2, "Invalid score 'x'".to_owned()));
+        assert_eq!(parse_error("A C G T\nA 1 0 0"), (2, "Expected 4 scores, but got 3".to_owned()));
+        assert_eq!(parse_error("A C G T\nU 1 0 0 0"), (2, "Row residue 'U' is not in the header".to_owned()));
+        assert_eq!(parse_error("A C\nA 1 0\nA 1 0\nC 0 1"), (3, "Duplicate row for 'A'".to_owned()));
+        assert_eq!(parse_error("A C G T\nA 1 0 0 0"), (3, "Missing row for 'C'".to_owned()));
+        assert_eq!(parse_error("AC G\nAC 1 0\nG 0 1"), (1, "Invalid residue 'AC'".to_owned()));
+    }
+
+    #[test]
+    fn reports_missing_residues() {
+        let (line, message) = parse_error("A C G\nA 1 0 0\nC 0 1 0\nG 0 0 1");
+        assert_eq!((line, message.as_str()), (1, "Residue 'T' of alphabet dna is missing"));
+    }
+
+    #[test]
+    fn rejects_matrices_for_other_residues() {
+        // A protein matrix for DNA (and a DNA matrix for proteins)
+        assert!(SubstitutionMatrix::builtin("BLOSUM62", Alphabet::Dna).unwrap().is_err());
+        assert!(SubstitutionMatrix::builtin("PAM250", Alphabet::IupacDna).unwrap().is_err());
+        assert!(SubstitutionMatrix::parse("A C G T\nA 1 0 0 0\nC 0 1 0 0\nG 0 0 1 0\nT 0 0 0 1", Alphabet::Protein).is_err());
+        assert!(SubstitutionMatrix::builtin("BLOSUM62", Alphabet::Protein).unwrap().is_ok());
+    }
+}
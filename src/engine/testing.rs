@@ -0,0 +1,145 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{alphabet::Alphabet, cigar::{Cigar, CigarStyle}, metrics::Metrics, model::{AlignedPair, Operation, Sequence}};
+
+use super::{AlignmentMode, Engine, FreeEndGaps, NaiveEngine, ScoringScheme, SubstitutionMatrix};
+
+/// A small pseudo-random number generator (xorshift), which
+/// keeps the tests reproducible without further dependencies.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    /// A number in the range 0..n.
+    pub fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+
+    /// A random DNA sequence with up to the given length (possibly empty).
+    pub fn sequence(&mut self, max_len: usize) -> Sequence {
+        let len = self.below(max_len + 1);
+        let residues = (0..len).map(|_| self.below(Alphabet::Dna.len()) as u8).collect();
+        Sequence::new("Random", Alphabet::Dna, residues)
+    }
+}
+
+/// The alignment modes to test the engines with.
+pub const MODES: [AlignmentMode; 4] = [
+    AlignmentMode::Local,
+    AlignmentMode::Global,
+    AlignmentMode::SemiGlobal(FreeEndGaps::QUERY),
+    AlignmentMode::SemiGlobal(FreeEndGaps::ALL),
+];
+
+/// The scoring schemes to test the engines with. The weights of the second
+/// one do not fit into 8 bits, thus the engines have to widen their scores.
+pub fn scoring_schemes() -> Vec<ScoringScheme> {
+    vec![
+        ScoringScheme::new(3, 1, SubstitutionMatrix::uniform(Alphabet::Dna, 2, -3)),
+        ScoringScheme::new(150, 50, SubstitutionMatrix::uniform(Alphabet::Dna, 100, -100)),
+    ]
+}
+
+/// Recomputes the score of the given alignment from its operations, where
+/// every run of insertions or deletions is a gap (costing the penalty for
+/// opening it plus the one for extending it by each further residue).
+pub fn rescore(scoring: &ScoringScheme, aligned: &AlignedPair) -> i32 {
+    let mut score = 0;
+    let mut previous = None;
+
+    for (k, &op) in aligned.operations.iter().enumerate() {
+        score += match op {
+            Operation::Match | Operation::Mismatch => {
+                let d = aligned.database.sequence[aligned.database.indices[k].unwrap()];
+                let q = aligned.query.sequence[aligned.query.indices[k].unwrap()];
+                assert_eq!(op == Operation::Match, d == q, "{:?} at {} in {}", op, k, aligned);
+                scoring.weight(d, q) as i32
+            },
+            _ if previous == Some(op) => -(scoring.gap_extend as i32),
+            _ => -(scoring.gap_open as i32),
+        };
+        previous = Some(op);
+    }
+
+    score
+}
+
+/// Asserts that the given alignment is consistent, i.e. that its score is
+/// the score of its operations, that it starts and ends where the mode allows
+/// and where its operations lead, and that it survives a round trip through
+/// CIGAR strings.
+pub fn assert_alignment_consistent(scoring: &ScoringScheme, mode: AlignmentMode, aligned: &AlignedPair) {
+    let (database, query) = (&aligned.database, &aligned.query);
+    let n = database.sequence.len();
+    let m = query.sequence.len();
+
+    assert_eq!(rescore(scoring, aligned), aligned.score, "{} {}", mode, aligned);
+
+    // The operations consume the residues between the start and the end
+    let consumed = |consumes: fn(Operation) -> bool| aligned.operations.iter().filter(|&&op| consumes(op)).count();
+    assert_eq!(database.end - database.start, consumed(Operation::consumes_database), "{} {}", mode, aligned);
+    assert_eq!(query.end - query.start, consumed(Operation::consumes_query), "{} {}", mode, aligned);
+    assert!(database.end <= n && query.end <= m, "{} {}", mode, aligned);
+
+    // (Semi-)global alignments start and end at the border, at
+    // the corners unless the corresponding end gaps are free
+    if let Some(gaps) = mode.free_end_gaps() {
+        assert!(database.start == 0 || query.start == 0, "{} {}", mode, aligned);
+        assert!(database.end == n || query.end == m, "{} {}", mode, aligned);
+        assert!(gaps.query_leading || database.start == 0, "{} {}", mode, aligned);
+        assert!(gaps.database_leading || query.start == 0, "{} {}", mode, aligned);
+        assert!(gaps.query_trailing || database.end == n, "{} {}", mode, aligned);
+        assert!(gaps.database_trailing || query.end == m, "{} {}", mode, aligned);
+    }
+
+    for style in [CigarStyle::Basic, CigarStyle::Extended] {
+        let cigar = Cigar::from_operations(&aligned.operations, style);
+        let operations = cigar.to_operations(database.sequence, database.start, query.sequence, query.start).unwrap();
+        assert_eq!(operations, aligned.operations, "{} {}", mode, cigar);
+    }
+}
+
+/// Asserts that the engines created by the given function compute the same
+/// scores as the naive engine on small random pairs (for every scoring scheme
+/// and mode), both for single pairs and for batches against the same database,
+/// and that their alignments are consistent (see `assert_alignment_consistent`).
+pub fn assert_matches_naive<E: Engine>(create: impl Fn(ScoringScheme, AlignmentMode) -> E) {
+    let metrics = Arc::new(Mutex::new(Metrics::new()));
+    let mut rng = Rng::new(42);
+
+    for scoring in scoring_schemes() {
+        for mode in MODES {
+            let naive = NaiveEngine::new(scoring.clone(), mode);
+            let engine = create(scoring.clone(), mode);
+
+            for _ in 0..8 {
+                let database = rng.sequence(40);
+                let queries: Vec<_> = (0..12).map(|_| rng.sequence(30)).collect();
+                let expected: Vec<_> = queries.iter().map(|query| naive.score(&database, query, &metrics).unwrap()).collect();
+
+                for (query, expected) in queries.iter().zip(&expected) {
+                    assert_eq!(&engine.score(&database, query, &metrics).unwrap(), expected, "{} {}", engine.name(), mode);
+
+                    let aligned = engine.align(&database, query, &metrics).unwrap();
+                    assert_eq!(aligned.score, expected.score, "{} {}", engine.name(), mode);
+                    assert_alignment_consistent(&scoring, mode, &aligned);
+                }
+
+                let scores: Vec<_> = engine.score_batch(&database, &queries, &metrics).into_iter().map(Result::unwrap).collect();
+                assert_eq!(scores, expected, "{} {}", engine.name(), mode);
+
+                for (aligned, expected) in engine.align_batch(&database, &queries, &metrics).into_iter().zip(&expected) {
+                    let aligned = aligned.unwrap();
+                    assert_eq!(aligned.score, expected.score, "{} {}", engine.name(), mode);
+                    assert_alignment_consistent(&scoring, mode, &aligned);
+                }
+            }
+        }
+    }
+}
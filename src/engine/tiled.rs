@@ -0,0 +1,346 @@
+use std::{ops::RangeInclusive, sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}}};
+use rayon::prelude::*;
+
+use crate::{model::{Sequence, AlignedPair, AlignmentScore}, metrics::Metrics, error::Error, utils::{Pool, UnsafeSlice}};
+
+use super::{AlignmentMode, Direction, Engine, Origin, Score, ScoringScheme, TracebackCell, Workspace, matrix_size, resized, traceback};
+
+/// An engine that computes alignments using the
+/// Smith-Waterman-Algorithm with a tiled wavefront
+/// on the CPU. The matrix is split into square tiles,
+/// whose anti-diagonals are computed in parallel,
+/// while each tile is computed sequentially (row by
+/// row). Compared to the diagonal engine, this trades
+/// some parallelism for much less scheduling overhead
+/// and better cache locality.
+pub struct TiledEngine {
+    scoring: ScoringScheme,
+    mode: AlignmentMode,
+    tile_size: usize,
+    workspaces: Pool,
+}
+
+impl TiledEngine {
+    /// The default edge length of the tiles, chosen such that
+    /// the tiles of all matrices comfortably fit into the cache.
+    pub const DEFAULT_TILE_SIZE: usize = 64;
+
+    /// Creates the engine, which fails if the tile size is zero.
+    pub fn new(scoring: ScoringScheme, mode: AlignmentMode, tile_size: usize) -> Result<Self, Error> {
+        if tile_size == 0 {
+            return Err(Error::InvalidTileSize);
+        }
+        Ok(Self { scoring, mode, tile_size, workspaces: Pool::new() })
+    }
+
+    /// Iterates over the anti-diagonals of the tiles covering the cells
+    /// (1, 1) to (n, m), calling the given function with the rows and the
+    /// columns of every tile. The tiles of an anti-diagonal are processed in
+    /// parallel, since they only depend on the tiles of the previous ones.
+    /// The function additionally receives the position (row, column) of the
+    /// tile in the grid of tiles.
+    fn wavefront(&self, n: usize, m: usize, tile: impl Fn((usize, usize), RangeInclusive<usize>, RangeInclusive<usize>) + Sync) {
+        let size = self.tile_size;
+        let tile_rows = n.div_ceil(size);
+        let tile_columns = m.div_ceil(size);
+
+        if tile_rows == 0 || tile_columns == 0 {
+            return;
+        }
+
+        for k in 0..(tile_rows + tile_columns - 1) {
+            // The bounds for the tile row, as for the cells in the diagonal engine
+            let lower = (k + 1).saturating_sub(tile_columns);
+            let upper = k.min(tile_rows - 1);
+
+            (lower..=upper).into_par_iter().for_each(|ti| {
+                let tj = k - ti;
+                let rows = (ti * size + 1)..=((ti + 1) * size).min(n);
+                let columns = (tj * size + 1)..=((tj + 1) * size).min(m);
+                tile((ti, tj), rows, columns);
+            });
+        }
+    }
+
+    /// Aligns the given two sequences using the given score type,
+    /// or returns none if any value of h saturates.
+    fn align_with<'a, S: Score>(&self, database: &'a Sequence, query: &'a Sequence) -> Result<Option<AlignedPair<'a>>, Error> {
+        if !S::fits(&self.scoring) {
+            return Ok(None);
+        }
+
+        let n = database.len();
+        let m = query.len();
+        let height = n + 1;
+        let width = m + 1;
+        let size = matrix_size(n, m)?;
+
+        // Create scoring matrix h, helper matrices e and f, a
+        // helper matrix p that tracks the direction the value came
+        // from and whether e and f extend a gap (rather than opening one)
+
+        let mut workspace = self.workspaces.take(Workspace::<S>::default);
+        let workspace = &mut *workspace;
+        let h = resized(&mut workspace.h, size)?;
+        let e = resized(&mut workspace.e, size)?;
+        let f = resized(&mut workspace.f, size)?;
+        let p = resized(&mut workspace.p, size)?;
+
+        // Initialize the borders (the leftmost column and the topmost row)
+
+        for i in 0..height {
+            (h[i * width], e[i * width], f[i * width]) = self.mode.border(&self.scoring, i, 0);
+        }
+        for j in 1..width {
+            (h[j], e[j], f[j]) = self.mode.border(&self.scoring, 0, j);
+        }
+
+        let uh = UnsafeSlice::new(h);
+        let ue = UnsafeSlice::new(e);
+        let uf = UnsafeSlice::new(f);
+        let up = UnsafeSlice::new(p);
+
+        let gap_open = S::saturating_from(self.scoring.gap_open as i32);
+        let gap_extend = S::saturating_from(self.scoring.gap_extend as i32);
+
+        // Perform scoring stage (dynamic programming-style). Within
+        // a tile, we iterate the cells like the naive engine does.
+
+        self.wavefront(n, m, |_, rows, columns| {
+            for i in rows {
+                for j in columns.clone() {
+                    // Compute indices of the neighboring cells
+                    let here = i * width + j;
+                    let above = (i - 1) * width + j;
+                    let left = i * width + j - 1;
+                    let above_left = (i - 1) * width + j - 1;
+
+                    unsafe {
+                        // Compute helper values
+                        let e_extend = ue.read(left).saturating_sub(gap_extend);
+                        let e_open = uh.read(left).saturating_sub(gap_open);
+                        let f_extend = uf.read(above).saturating_sub(gap_extend);
+                        let f_open = uh.read(above).saturating_sub(gap_open);
+
+                        ue.write(here, e_extend.max(e_open));
+                        uf.write(here, f_extend.max(f_open));
+
+                        // Compute value and remember the direction the maximum came from
+                        // (we need this later for the traceback phase)
+                        let (max_origin, max_value) = [
+                            (Origin::Start,    self.mode.min_score()),
+                            (Origin::Diagonal, uh.read(above_left).saturating_add(S::saturating_from(self.scoring.weight(database[i - 1], query[j - 1]) as i32))),
+                            (Origin::Left,     ue.read(here)),
+                            (Origin::Above,    uf.read(here)),
+                        ].into_iter().max_by_key(|&(_, x)| x).unwrap();
+
+                        uh.write(here, max_value);
+                        up.write(here, Direction::new(max_origin, e_extend > e_open, f_extend > f_open));
+                    }
+                }
+            }
+        });
+
+        // The values (and thus the traceback) are only exact if none of them saturated
+
+        if h.iter().any(|x| x.is_saturated()) {
+            return Ok(None);
+        }
+
+        // Perform traceback stage (using the previously computed scoring matrix h)
+
+        let end = match self.mode.free_end_gaps() {
+            Some(gaps) => gaps.end(n, m, |i, j| h[i * width + j]),
+            None => {
+                let end = (0..size).max_by_key(|&i| h[i]).unwrap();
+                (end / width, end % width)
+            },
+        };
+        Ok(Some(traceback(database, query, self.mode, end, |i, j| i * width + j, |i| TracebackCell {
+            score: h[i].into(),
+            direction: p[i],
+        })))
+    }
+
+    /// Computes the score of the alignment and where it ends using the given
+    /// score type, or returns none if any value of h saturates.
+    ///
+    /// Rather than the entire matrix, we only keep the last row of each tile
+    /// (for the tile below) and the last column (for the tile to the right).
+    /// Since a tile reads the row written by the tile above (one anti-diagonal
+    /// ago) and its above left corner from the tile above left (two anti-diagonals
+    /// ago), we rotate between three buffers for the rows (indexed by the tile
+    /// row) and likewise for the columns. Thus, no tile overwrites values that a
+    /// tile of the same anti-diagonal still needs, and this needs linear memory.
+    fn score_with<S: Score>(&self, database: &Sequence, query: &Sequence) -> Option<AlignmentScore> {
+        if !S::fits(&self.scoring) {
+            return None;
+        }
+
+        let n = database.len();
+        let m = query.len();
+        let height = n + 1;
+        let width = m + 1;
+
+        // Compute the borders (the topmost row and the leftmost column) of h.
+        // The borders of e and f are always negative infinity.
+
+        let border_row: Vec<S> = (0..width).map(|j| self.mode.border(&self.scoring, 0, j).0).collect();
+        let border_column: Vec<S> = (0..height).map(|i| self.mode.border(&self.scoring, i, 0).0).collect();
+
+        // Create the buffers for the rows (h and f) and the columns (h and e), with the
+        // first ones holding the borders. The rows include the leftmost column to
+        // provide the corner for the tiles in the first tile column.
+
+        let mut row_h: Vec<_> = border_row.iter().copied().chain(vec![S::ZERO; 2 * width]).collect();
+        let mut row_f: Vec<_> = vec![S::NEGATIVE_INFINITY; width].into_iter().chain(vec![S::ZERO; 2 * width]).collect();
+        let mut column_h: Vec<_> = border_column.iter().copied().chain(vec![S::ZERO; 2 * height]).collect();
+        let mut column_e: Vec<_> = vec![S::NEGATIVE_INFINITY; height].into_iter().chain(vec![S::ZERO; 2 * height]).collect();
+
+        // To find the end of the alignment, we track the maximum
+        // of each row and the last row and column.
+
+        let mut row_maxima: Vec<_> = border_column.iter().map(|&x| (x, 0)).collect();
+        let mut last_row = border_row.clone();
+        let mut last_column = border_column.clone();
+
+        row_maxima[0] = (0..width).map(|j| (border_row[j], j)).max_by_key(|&(x, _)| x).unwrap();
+        last_row[0] = border_column[n];
+        last_column[0] = border_row[m];
+
+        let saturated = AtomicBool::new(border_row.iter().chain(&border_column).any(|x| x.is_saturated()));
+        let gap_open = S::saturating_from(self.scoring.gap_open as i32);
+        let gap_extend = S::saturating_from(self.scoring.gap_extend as i32);
+
+        let urow_h = UnsafeSlice::new(&mut row_h);
+        let urow_f = UnsafeSlice::new(&mut row_f);
+        let ucolumn_h = UnsafeSlice::new(&mut column_h);
+        let ucolumn_e = UnsafeSlice::new(&mut column_e);
+        let urow_maxima = UnsafeSlice::new(&mut row_maxima);
+        let ulast_row = UnsafeSlice::new(&mut last_row);
+        let ulast_column = UnsafeSlice::new(&mut last_column);
+
+        // Perform scoring stage (dynamic programming-style)
+
+        self.wavefront(n, m, |(ti, tj), rows, columns| {
+            let bottom = *rows.end();
+            let (left, right) = (*columns.start(), *columns.end());
+            let row_in = (ti % 3) * width;
+            let row_out = ((ti + 1) % 3) * width;
+            let column_in = (tj % 3) * height;
+            let column_out = ((tj + 1) % 3) * height;
+
+            unsafe {
+                // Copy the row above the tile (including the corner above left), which
+                // we then update in-place like in score_rows (while iterating the rows).
+                let mut h: Vec<_> = ((left - 1)..=right).map(|j| urow_h.read(row_in + j)).collect();
+                let mut f: Vec<_> = ((left - 1)..=right).map(|j| urow_f.read(row_in + j)).collect();
+                let mut tile_saturated = false;
+
+                for i in rows {
+                    let mut h_above_left = h[0];
+                    let mut e_here = ucolumn_e.read(column_in + i);
+                    h[0] = ucolumn_h.read(column_in + i);
+                    let mut row_maximum = urow_maxima.read(i);
+
+                    for (c, j) in columns.clone().enumerate().map(|(c, j)| (c + 1, j)) {
+                        // Compute helper values (h and f still hold the values above)
+                        e_here = e_here.saturating_sub(gap_extend).max(h[c - 1].saturating_sub(gap_open));
+                        f[c] = f[c].saturating_sub(gap_extend).max(h[c].saturating_sub(gap_open));
+
+                        // Compute value
+                        let h_here = h_above_left.saturating_add(S::saturating_from(self.scoring.weight(database[i - 1], query[j - 1]) as i32))
+                            .max(e_here)
+                            .max(f[c])
+                            .max(self.mode.min_score());
+
+                        h_above_left = h[c];
+                        h[c] = h_here;
+                        tile_saturated |= h_here.is_saturated();
+
+                        if h_here >= row_maximum.0 {
+                            row_maximum = (h_here, j);
+                        }
+                        if i == n {
+                            ulast_row.write(j, h_here);
+                        }
+                    }
+
+                    // Since the tiles of an anti-diagonal are in different rows
+                    // (and columns), we can track the maxima without synchronization.
+                    urow_maxima.write(i, row_maximum);
+                    ucolumn_h.write(column_out + i, h[right - left + 1]);
+                    ucolumn_e.write(column_out + i, e_here);
+                    if right == m {
+                        ulast_column.write(i, h[right - left + 1]);
+                    }
+                }
+
+                for (c, j) in (left..=right).enumerate().map(|(c, j)| (c + 1, j)) {
+                    urow_h.write(row_out + j, h[c]);
+                    urow_f.write(row_out + j, f[c]);
+                }
+                if tj == 0 {
+                    urow_h.write(row_out, border_column[bottom]);
+                }
+
+                if tile_saturated {
+                    saturated.store(true, Ordering::Relaxed);
+                }
+            }
+
+        });
+
+        if saturated.into_inner() {
+            return None;
+        }
+
+        Some(self.mode.find_end(&row_maxima, &last_row, &last_column))
+    }
+}
+
+impl Engine for TiledEngine {
+    fn name(&self) -> String {
+        format!("Tiled (CPU, {}x{})", self.tile_size, self.tile_size)
+    }
+
+    fn align<'a>(&self, database: &'a Sequence, query: &'a Sequence, metrics: &Arc<Mutex<Metrics>>) -> Result<AlignedPair<'a>, Error> {
+        // Start with 8-bit scores and widen them if the alignment saturates
+        let aligned = self.align_with::<i8>(database, query).transpose()
+            .or_else(|| self.align_with::<i16>(database, query).transpose())
+            .or_else(|| self.align_with::<i32>(database, query).transpose())
+            .unwrap_or(Err(Error::ScoreOverflow))?;
+
+        metrics.lock().unwrap().record_cell_updates(4 * (database.len() + 1) * (query.len() + 1));
+        metrics.lock().unwrap().record_sequence_pair();
+
+        Ok(aligned)
+    }
+
+    fn score(&self, database: &Sequence, query: &Sequence, metrics: &Arc<Mutex<Metrics>>) -> Result<AlignmentScore, Error> {
+        let score = self.score_with::<i8>(database, query)
+            .or_else(|| self.score_with::<i16>(database, query))
+            .or_else(|| self.score_with::<i32>(database, query))
+            .ok_or(Error::ScoreOverflow)?;
+
+        metrics.lock().unwrap().record_cell_updates(4 * (database.len() + 1) * (query.len() + 1));
+        metrics.lock().unwrap().record_sequence_pair();
+
+        Ok(score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::testing::assert_matches_naive;
+
+    use super::TiledEngine;
+
+    #[test]
+    fn matches_naive() {
+        // Tiles of a single cell, tiles that do not divide the matrix and a single tile
+        for tile_size in [1, 7, 64] {
+            assert_matches_naive(|scoring, mode| TiledEngine::new(scoring, mode, tile_size).unwrap());
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use crate::model::{Sequence, Operation, AlignedPair};
+
+use super::AlignmentMode;
+
+/// The matrix (from Gotoh's algorithm) that the
+/// traceback is currently following.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// The scoring matrix h.
+    H,
+    /// The matrix e of gaps in the database (i.e. insertions).
+    E,
+    /// The matrix f of gaps in the query (i.e. deletions).
+    F,
+}
+
+/// The cell that the value of h came from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum Origin {
+    /// The minimum score (i.e. a local alignment starts here).
+    Start = 0,
+    /// The cell above left (aligning two residues).
+    Diagonal = 1,
+    /// The value of e (i.e. the cell to the left).
+    Left = 2,
+    /// The value of f (i.e. the cell above).
+    Above = 3,
+}
+
+/// The traceback data of a cell, packed into a single byte: The
+/// lowest two bits hold the origin of h and the next two bits whether
+/// e and f extend a gap (rather than opening one). The OpenCL kernels
+/// use the same encoding (see direction.cl).
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[repr(transparent)]
+pub struct Direction(u8);
+
+impl Direction {
+    const ORIGIN_MASK: u8 = 0b11;
+    const E_EXTENDED: u8 = 1 << 2;
+    const F_EXTENDED: u8 = 1 << 3;
+
+    pub fn new(origin: Origin, e_extended: bool, f_extended: bool) -> Self {
+        Self(origin as u8 | if e_extended { Self::E_EXTENDED } else { 0 } | if f_extended { Self::F_EXTENDED } else { 0 })
+    }
+
+    /// The cell that the value of h came from.
+    pub fn origin(self) -> Origin {
+        match self.0 & Self::ORIGIN_MASK {
+            0 => Origin::Start,
+            1 => Origin::Diagonal,
+            2 => Origin::Left,
+            _ => Origin::Above,
+        }
+    }
+
+    /// Whether e extends a gap (rather than opening one).
+    pub fn e_extended(self) -> bool {
+        self.0 & Self::E_EXTENDED != 0
+    }
+
+    /// Whether f extends a gap (rather than opening one).
+    pub fn f_extended(self) -> bool {
+        self.0 & Self::F_EXTENDED != 0
+    }
+}
+
+/// The data recorded for a cell during the scoring stage
+/// that the traceback needs.
+pub struct TracebackCell {
+    /// The value of h.
+    pub score: i32,
+    /// Where the value of h came from and the gap flags.
+    pub direction: Direction,
+}
+
+/// Traces back the alignment ending at the given cell (i, j).
+/// Note that the cell (i, j) corresponds to the residues i - 1
+/// and j - 1 of the database and query, respectively.
+///
+/// Since the origin of h alone cannot tell whether a cell was
+/// reached by opening or by extending a gap, this follows the three
+/// states of Gotoh's algorithm: In state H, we follow the origin of
+/// h, which either aligns two residues or switches to one of the gap
+/// states E and F. These, in turn, follow the gap until the cell's
+/// flag indicates that it was opened, switching back to state H.
+///
+/// Local alignments stop as soon as h drops to 0, whereas (semi-)global
+/// alignments continue along the border until they reach (0, 0), unless
+/// the corresponding leading gaps are free.
+pub fn traceback<'a>(
+    database: &'a Sequence,
+    query: &'a Sequence,
+    mode: AlignmentMode,
+    end: (usize, usize),
+    index: impl Fn(usize, usize) -> usize,
+    cell: impl Fn(usize) -> TracebackCell,
+) -> AlignedPair<'a> {
+    let score = cell(index(end.0, end.1)).score;
+    let (mut i, mut j) = end;
+    let mut state = State::H;
+    let mut operations = Vec::new();
+
+    let gaps = mode.free_end_gaps();
+    let global = gaps.is_some();
+
+    loop {
+        let here = cell(index(i, j));
+        match state {
+            // The border of (semi-)global alignments is a leading gap
+            _ if matches!(gaps, Some(g) if !g.database_leading) && i == 0 && j > 0 => {
+                operations.push(Operation::Insertion);
+                j -= 1;
+            },
+            _ if matches!(gaps, Some(g) if !g.query_leading) && j == 0 && i > 0 => {
+                operations.push(Operation::Deletion);
+                i -= 1;
+            },
+            State::H if i > 0 && j > 0 && (global || here.score > 0) => {
+                match here.direction.origin() {
+                    Origin::Left => state = State::E,
+                    Origin::Above => state = State::F,
+                    _ => {
+                        operations.push(if database[i - 1] == query[j - 1] { Operation::Match } else { Operation::Mismatch });
+                        i -= 1;
+                        j -= 1;
+                    },
+                }
+            },
+            State::E if j > 0 => {
+                operations.push(Operation::Insertion);
+                if !here.direction.e_extended() {
+                    state = State::H;
+                }
+                j -= 1;
+            },
+            State::F if i > 0 => {
+                operations.push(Operation::Deletion);
+                if !here.direction.f_extended() {
+                    state = State::H;
+                }
+                i -= 1;
+            },
+            _ => break,
+        }
+    }
+
+    operations.reverse();
+
+    AlignedPair::new(database, query, i, j, operations, score)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{alphabet::Alphabet, cigar::CigarStyle, metrics::Metrics, model::Sequence};
+    use crate::engine::{AlignmentMode, DiagonalEngine, Engine, NaiveEngine, OptimizedDiagonalEngine, ScoringScheme, SubstitutionMatrix, score_rows};
+    use crate::engine::testing::{MODES, assert_alignment_consistent, assert_matches_naive};
+
+    /// The engines that trace back their alignments with `traceback`.
+    fn engines(scoring: &ScoringScheme, mode: AlignmentMode) -> Vec<Box<dyn Engine>> {
+        vec![
+            Box::new(NaiveEngine::new(scoring.clone(), mode)),
+            Box::new(DiagonalEngine::new(scoring.clone(), mode)),
+            Box::new(OptimizedDiagonalEngine::new(scoring.clone(), mode)),
+        ]
+    }
+
+    /// Aligns the given sequences with every engine (and mode), asserting that
+    /// the alignments are optimal and consistent, and returns their CIGAR strings.
+    fn align(scoring: &ScoringScheme, database: &[u8], query: &[u8]) -> Vec<String> {
+        let metrics = Arc::new(Mutex::new(Metrics::new()));
+        let database = Sequence::parse("Database", Alphabet::Dna, database).unwrap();
+        let query = Sequence::parse("Query", Alphabet::Dna, query).unwrap();
+        let mut cigars = Vec::new();
+
+        for mode in MODES {
+            let optimum = score_rows(scoring, mode, &database, &query).unwrap().score;
+            for engine in engines(scoring, mode) {
+                let aligned = engine.align(&database, &query, &metrics).unwrap();
+                assert_eq!(aligned.score, optimum, "{} {}", engine.name(), mode);
+                assert_alignment_consistent(scoring, mode, &aligned);
+                cigars.push(aligned.cigar(CigarStyle::Extended).to_string());
+            }
+        }
+
+        cigars
+    }
+
+    #[test]
+    fn traces_back_long_gaps() {
+        // A gap of 8 residues costs 6 + 7 * 1, thus it is worth bridging in every mode
+        let scoring = ScoringScheme::new(6, 1, SubstitutionMatrix::uniform(Alphabet::Dna, 2, -3));
+
+        for cigar in align(&scoring, b"ACGTCAGGTCTTTTTTTTGATCCATGCA", b"ACGTCAGGTCGATCCATGCA") {
+            assert_eq!(cigar, "10=8D10=");
+        }
+        for cigar in align(&scoring, b"ACGTCAGGTCGATCCATGCA", b"ACGTCAGGTCTTTTTTTTGATCCATGCA") {
+            assert_eq!(cigar, "10=8I10=");
+        }
+    }
+
+    #[test]
+    fn extends_gaps_rather_than_reopening() {
+        // Matching the A in the middle of the gap splits it into two gaps, which
+        // costs 2 * (8 + 3 * 1) - 2 = 20, whereas a single gap of 8 residues and a
+        // mismatch cost 8 + 7 * 1 + 3 = 18. Thus the traceback has to extend
+        // the gap rather than re-opening it around the match.
+        let scoring = ScoringScheme::new(8, 1, SubstitutionMatrix::uniform(Alphabet::Dna, 2, -3));
+
+        for cigar in align(&scoring, b"ACGTCAGGTCTTTTATTTTGATCCATGCA", b"ACGTCAGGTCAGATCCATGCA") {
+            assert!(cigar == "10=8D1X10=" || cigar == "10=1X8D10=", "{}", cigar);
+        }
+        for cigar in align(&scoring, b"ACGTCAGGTCAGATCCATGCA", b"ACGTCAGGTCTTTTATTTTGATCCATGCA") {
+            assert!(cigar == "10=8I1X10=" || cigar == "10=1X8I10=", "{}", cigar);
+        }
+    }
+
+    #[test]
+    fn alignments_match_naive() {
+        assert_matches_naive(NaiveEngine::new);
+        assert_matches_naive(DiagonalEngine::new);
+        assert_matches_naive(OptimizedDiagonalEngine::new);
+    }
+}
@@ -0,0 +1,41 @@
+use std::mem;
+
+use crate::error::Error;
+
+use super::Direction;
+
+/// The matrices that an engine computes for the traceback, which are
+/// kept in a pool (see `Pool`) and reused across alignments rather than
+/// allocated for every alignment. Since the matrices only ever grow, they
+/// may still hold the values of earlier alignments, thus the engines need
+/// to write every cell before reading it.
+pub struct Workspace<S> {
+    pub h: Vec<S>,
+    pub e: Vec<S>,
+    pub f: Vec<S>,
+    pub p: Vec<Direction>,
+}
+
+impl<S> Default for Workspace<S> {
+    fn default() -> Self {
+        Self { h: Vec::new(), e: Vec::new(), f: Vec::new(), p: Vec::new() }
+    }
+}
+
+/// The number of cells of the matrices for aligning sequences of the
+/// given lengths (including the borders), unless it overflows.
+pub fn matrix_size(n: usize, m: usize) -> Result<usize, Error> {
+    (n + 1).checked_mul(m + 1).ok_or(Error::MatrixTooLarge { database: n, query: m })
+}
+
+/// Grows the given buffer to (at least) the given size if needed and
+/// returns its first cells, failing (rather than aborting) if the
+/// memory cannot be allocated.
+pub fn resized<T: Copy + Default>(buffer: &mut Vec<T>, size: usize) -> Result<&mut [T], Error> {
+    if buffer.len() < size {
+        buffer.try_reserve_exact(size - buffer.len())
+            .map_err(|_| Error::OutOfMemory { bytes: size.saturating_mul(mem::size_of::<T>()) })?;
+        buffer.resize(size, T::default());
+    }
+    Ok(&mut buffer[..size])
+}
@@ -0,0 +1,87 @@
+use std::{fmt, io};
+
+use crate::{alphabet::InvalidResiduesError, engine::{InstructionSet, OpenCLDeviceType}};
+
+/// The errors that may occur while creating the engines, reading
+/// sequences or aligning them. Unlike panics, these let callers
+/// skip a bad pair (or engine) and carry on with the rest. Errors
+/// of OpenCL and I/O are kept as their messages, so that a failure
+/// shared by a batch can be reported for each of its pairs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// A call to OpenCL failed (e.g. building a program,
+    /// allocating a buffer or enqueuing a kernel).
+    OpenCL(String),
+    /// The selected OpenCL platform does not exist.
+    PlatformNotFound { index: usize, available: usize },
+    /// The selected OpenCL device does not exist on the platform.
+    DeviceNotFound { platform_index: usize, device_type: OpenCLDeviceType, index: usize, available: usize },
+    /// The selected SIMD instruction set is not supported by the CPU.
+    UnsupportedInstructionSet(InstructionSet),
+    /// The tile size (of the tiled engine) is zero.
+    InvalidTileSize,
+    /// The matrices for aligning sequences of the given lengths are
+    /// larger than the address space (or a buffer on the device).
+    MatrixTooLarge { database: usize, query: usize },
+    /// Allocating the given number of bytes (for the matrices) failed.
+    OutOfMemory { bytes: usize },
+    /// The alignment overflows even with 32-bit scores.
+    ScoreOverflow,
+    /// A sequence contains residues that are not part of its alphabet.
+    InvalidResidues { sequence: String, error: InvalidResiduesError },
+    /// A FASTA file is misformatted (at the given line).
+    InvalidFasta { line: usize, message: String },
+    /// Reading the input failed.
+    Io(String),
+    /// The results of an engine do not agree with each other (e.g. an
+    /// alignment with its CIGAR string or its score with the score-only one).
+    InconsistentResult(String),
+}
+
+impl From<ocl::Error> for Error {
+    fn from(error: ocl::Error) -> Self {
+        Error::OpenCL(error.to_string())
+    }
+}
+
+impl From<ocl::core::Error> for Error {
+    fn from(error: ocl::core::Error) -> Self {
+        Error::OpenCL(error.to_string())
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error.to_string())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::OpenCL(error) => write!(f, "OpenCL failed: {}", error),
+            Error::PlatformNotFound { index, available } => write!(
+                f, "OpenCL platform {} not found ({} available, see the devices command)",
+                index, available
+            ),
+            Error::DeviceNotFound { platform_index, device_type, index, available } => write!(
+                f, "OpenCL {} {} not found on platform {} ({} available, see the devices command)",
+                device_type, index, platform_index, available
+            ),
+            Error::UnsupportedInstructionSet(instruction_set) => write!(f, "The instruction set {} is not supported by this CPU", instruction_set),
+            Error::InvalidTileSize => write!(f, "The tile size must be positive"),
+            Error::MatrixTooLarge { database, query } => write!(
+                f, "The matrices for aligning sequences of lengths {} and {} are too large",
+                database, query
+            ),
+            Error::OutOfMemory { bytes } => write!(f, "Could not allocate {} bytes for the matrices", bytes),
+            Error::ScoreOverflow => write!(f, "The alignment overflows even with 32-bit scores"),
+            Error::InvalidResidues { sequence, error } => write!(f, "Sequence {} is invalid: {}", sequence, error),
+            Error::InvalidFasta { line, message } => write!(f, "Misformatted FASTA file, {} (at line {})", message, line),
+            Error::Io(error) => write!(f, "Could not read input: {}", error),
+            Error::InconsistentResult(message) => write!(f, "Inconsistent result: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
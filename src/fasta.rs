@@ -1,26 +1,25 @@
 use std::io::BufRead;
 
-use crate::model::Sequence;
+use crate::{alphabet::Alphabet, error::Error, model::Sequence};
 
 const PREFIX: char = '>';
 
-/// An abstraction for reading nucleid sequences in the FASTA
-/// format from an underlying reader (e.g. a file).
+/// An abstraction for reading nucleid or protein sequences in
+/// the FASTA format from an underlying reader (e.g. a file).
 pub struct FastaReader<R> {
     reader: R,
+    alphabet: Alphabet,
     line_index: usize,
     buffer: String,
     done: bool,
 }
 
 impl<R> FastaReader<R> where R: BufRead {
-    pub fn new(reader: R) -> Self {
-        let mut new = Self { reader, line_index: 0, buffer: String::with_capacity(128), done: false };
-        new.next_line();
-        new
+    pub fn new(reader: R, alphabet: Alphabet) -> Self {
+        Self { reader, alphabet, line_index: 0, buffer: String::with_capacity(128), done: false }
     }
 
-    fn current_line(&mut self) -> Option<&str> {
+    fn current_line(&self) -> Option<&str> {
         if self.done {
             None
         } else {
@@ -28,37 +27,65 @@ impl<R> FastaReader<R> where R: BufRead {
         }
     }
 
-    fn next_line(&mut self) -> Option<&str> {
+    fn next_line(&mut self) -> Result<Option<&str>, Error> {
         if self.done {
-            return None;
+            return Ok(None);
         }
         self.buffer.clear();
-        let byte_count = self.reader.read_line(&mut self.buffer).expect("Could not read line from FASTA file.");
+        let byte_count = match self.reader.read_line(&mut self.buffer) {
+            Ok(byte_count) => byte_count,
+            Err(e) => {
+                // We cannot tell where the next sequence starts, thus we stop
+                self.done = true;
+                return Err(e.into());
+            },
+        };
+        self.line_index += 1;
         if byte_count == 0 {
             self.done = true;
         }
-        self.current_line()
+        Ok(self.current_line())
     }
-}
 
-impl<R> Iterator for FastaReader<R> where R: BufRead {
-    type Item = Sequence;
+    /// Reads the next sequence, i.e. the header line and the lines up
+    /// to the next header (or the end of the file). After an error,
+    /// reading continues with the next sequence.
+    fn next_sequence(&mut self) -> Result<Option<Sequence>, Error> {
+        if self.line_index == 0 {
+            self.next_line()?;
+        }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let mut line = self.current_line()?;
-        let mut raw: Vec<u8> = Vec::with_capacity(64);
+        let name = match self.current_line() {
+            Some(line) => line.strip_prefix(PREFIX).map(|name| name.to_owned()),
+            None => return Ok(None),
+        };
+        let name = match name {
+            Some(name) => name,
+            None => {
+                let line = self.line_index;
+                self.next_line()?;
+                return Err(Error::InvalidFasta { line, message: format!("line does not begin with {}", PREFIX) });
+            },
+        };
 
-        if let Some(name) = line.strip_prefix(PREFIX).map(|s| s.to_owned()) {
-            loop {
-                line = self.next_line()?;
-                if line.starts_with(PREFIX) {
-                    break Some(Sequence::new(name.as_str(), raw));
-                } else {
-                    raw.extend(line.as_bytes());
-                }
+        let mut raw: Vec<u8> = Vec::with_capacity(64);
+        while let Some(line) = self.next_line()? {
+            if line.starts_with(PREFIX) {
+                break;
             }
-        } else {
-            panic!("Misformatted FASTA file, line does not begin with {} (at line {})", PREFIX, self.line_index);
+            raw.extend(line.as_bytes());
         }
+
+        Sequence::parse(name.as_str(), self.alphabet, &raw)
+            .map(Some)
+            .map_err(|error| Error::InvalidResidues { sequence: name, error })
+    }
+}
+
+impl<R> Iterator for FastaReader<R> where R: BufRead {
+    type Item = Result<Sequence, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_sequence().transpose()
     }
 }
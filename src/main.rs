@@ -1,74 +1,333 @@
+mod alphabet;
+mod cigar;
 mod engine;
+mod error;
 mod fasta;
 mod metrics;
 mod model;
 mod utils;
 
-use clap::{Parser, Subcommand};
-use std::{io::{BufReader, self, Write}, fs::File, sync::{Mutex, Arc}};
-use rayon::prelude::*;
+use clap::{Args, Parser, Subcommand};
+use std::{fmt, io::{BufReader, self, Write}, fs::{self, File}, process, slice, sync::{Mutex, Arc}};
+use ocl::{Device, enums::DeviceInfo};
 
-use engine::{NaiveEngine, Engine, DiagonalEngine, OptimizedDiagonalEngine, OptimizedOpenCLDiagonalEngine};
+use engine::{NaiveEngine, Engine, DiagonalEngine, OptimizedDiagonalEngine, OpenCLInterTaskEngine, HirschbergEngine, StripedEngine, InterSequenceEngine, SearchResult, TiledEngine, InstructionSet, OpenCLDeviceSelector, OpenCLDeviceType, opencl_platforms, ScoringScheme, SubstitutionMatrix, AlignmentMode, FreeEndGaps};
+use alphabet::Alphabet;
+use cigar::{CigarError, CigarStyle};
+use error::Error;
 use fasta::FastaReader;
 use metrics::Metrics;
-use model::{Sequence, AlignedPair};
+use model::{Sequence, AlignedPair, AlignmentScore};
 
 use crate::{utils::{pretty_box, EqualAsserter}, engine::OpenCLDiagonalEngine};
 
-fn run<'a>(engine: &impl Engine, database: &'a Sequence, query: &'a Sequence) -> AlignedPair<'a> {
+fn run(engine: &impl Engine, database: &Sequence, query: &Sequence) {
     println!("{}", pretty_box(engine.name()));
 
-    let aligned = engine.align(database, query, &Arc::new(Mutex::new(Metrics::new())));
+    // Report a failing engine without giving up on the others
+    if let Err(e) = run_pair(engine, database, query) {
+        eprintln!("Error: {}", e);
+    }
+}
+
+fn run_pair(engine: &impl Engine, database: &Sequence, query: &Sequence) -> Result<(), Error> {
+    let metrics = Arc::new(Mutex::new(Metrics::new()));
+    let aligned = engine.align(database, query, &metrics)?;
     println!("D: {}", aligned.database);
     println!("Q: {}", aligned.query);
+    println!("Score: {}, length: {}, database: {}..{}, query: {}..{}",
+        aligned.score, aligned.len(),
+        aligned.database.start, aligned.database.end,
+        aligned.query.start, aligned.query.end);
+    println!("CIGAR: {} ({})", aligned.cigar(CigarStyle::Basic), aligned.cigar(CigarStyle::Extended));
+
+    // Verify that the (basic) CIGAR string describes the same alignment
+    let inconsistent = |e: CigarError| Error::InconsistentResult(format!("Invalid CIGAR string: {}", e));
+    let cigar = aligned.cigar(CigarStyle::Basic).to_string().parse().map_err(inconsistent)?;
+    let parsed = AlignedPair::from_cigar(database, query, aligned.database.start, aligned.query.start, &cigar, aligned.score).map_err(inconsistent)?;
+    if parsed != aligned {
+        return Err(Error::InconsistentResult("The CIGAR string describes a different alignment".to_owned()));
+    }
+
+    // Verify that the score-only computation yields the same score
+    let score = engine.score(database, query, &metrics)?;
+    println!("Score only: {}", score);
+    if score.score != aligned.score {
+        return Err(Error::InconsistentResult(format!("The score-only computation yields {} rather than {}", score.score, aligned.score)));
+    }
+
+    Ok(())
+}
+
+/// The result of benchmarking a single pair of sequences.
+#[derive(Debug, PartialEq, Eq)]
+enum BenchResult<'a> {
+    Alignment(AlignedPair<'a>),
+    Score(AlignmentScore),
+    Failed(Error),
+}
 
-    aligned
+impl<'a> From<Result<AlignedPair<'a>, Error>> for BenchResult<'a> {
+    fn from(result: Result<AlignedPair<'a>, Error>) -> Self {
+        result.map_or_else(BenchResult::Failed, BenchResult::Alignment)
+    }
+}
+
+impl<'a> From<Result<AlignmentScore, Error>> for BenchResult<'a> {
+    fn from(result: Result<AlignmentScore, Error>) -> Self {
+        result.map_or_else(BenchResult::Failed, BenchResult::Score)
+    }
+}
+
+fn bench_pair<'a>(engine: &impl Engine, database: &'a Sequence, query: &'a Sequence, score_only: bool, metrics: &Arc<Mutex<Metrics>>) -> BenchResult<'a> {
+    if score_only {
+        engine.score(database, query, metrics).into()
+    } else {
+        engine.align(database, query, metrics).into()
+    }
+}
+
+/// Prints how many pairs failed (along with the first error), if any.
+fn print_failures(results: &[BenchResult]) {
+    let mut errors = results.iter().filter_map(|result| match result {
+        BenchResult::Failed(e) => Some(e),
+        _ => None,
+    });
+    if let Some(first) = errors.next() {
+        eprintln!("Failed pairs: {} (e.g. {})", errors.count() + 1, first);
+    }
 }
 
-fn bench_sequential<'a>(engine: &impl Engine, database: &'a Sequence, queries: &'a Vec<Sequence>) -> Vec<AlignedPair<'a>> {
+fn bench_sequential<'a>(engine: &impl Engine, database: &'a Sequence, queries: &'a [Sequence], score_only: bool) -> Vec<BenchResult<'a>> {
     println!("{}", pretty_box(format!("{} (sequential)", engine.name())));
 
     let total = queries.len();
     let metrics = Arc::new(Mutex::new(Metrics::new()));
-    let aligns = queries.iter().enumerate().map(|(i, query)| {
-        let aligned = engine.align(database, query, &metrics);
+    let aligns: Vec<_> = queries.iter().enumerate().map(|(i, query)| {
+        let result = bench_pair(engine, database, query, score_only, &metrics);
         if i % 100 == 0 {
             print!("\r[{} %]", (i * 100) / total);
             io::stdout().flush().unwrap();
         }
-        aligned
+        result
     }).collect();
 
     print!("\r");
     metrics.lock().unwrap().print();
+    print_failures(&aligns);
     aligns
 }
 
-fn bench_parallel<'a>(engine: &(impl Engine + Sync), database: &'a Sequence, queries: &'a Vec<Sequence>) -> Vec<AlignedPair<'a>> {
+fn bench_parallel<'a>(engine: &impl Engine, database: &'a Sequence, queries: &'a [Sequence], score_only: bool) -> Vec<BenchResult<'a>> {
     println!("{}", pretty_box(format!("{} (parallel)", engine.name())));
 
+    // The alignments are computed as a batch, which lets
+    // the engine share work across the pairs.
     let metrics = Arc::new(Mutex::new(Metrics::new()));
-    let aligns = queries.par_iter().map(|query| {
-        engine.align(database, query, &metrics)
+    let aligns: Vec<_> = if score_only {
+        engine.score_batch(database, queries, &metrics).into_iter().map(BenchResult::from).collect()
+    } else {
+        engine.align_batch(database, queries, &metrics).into_iter().map(BenchResult::from).collect()
+    };
+
+    metrics.lock().unwrap().print();
+    print_failures(&aligns);
+    aligns
+}
+
+fn bench_search<'a>(engine: &InterSequenceEngine, database: &'a Sequence, queries: &'a [Sequence], score_only: bool) -> Vec<BenchResult<'a>> {
+    println!("{}", pretty_box(format!("{} (parallel)", engine.name())));
+
+    // Since every query is a hit (if we need the alignments),
+    // the traceback is performed for all of them.
+    let metrics = Arc::new(Mutex::new(Metrics::new()));
+    let min_score = if score_only { None } else { Some(i32::MIN) };
+    let aligns: Vec<_> = engine.search(database, queries, min_score, &metrics).into_iter().map(|result| match result {
+        Ok(SearchResult { alignment: Some(alignment), .. }) => BenchResult::Alignment(alignment),
+        Ok(SearchResult { score, alignment: None }) => BenchResult::Score(score),
+        Err(e) => BenchResult::Failed(e),
     }).collect();
 
     metrics.lock().unwrap().print();
+    print_failures(&aligns);
     aligns
 }
 
+/// Prints the given error and exits.
+fn fail(error: impl fmt::Display) -> ! {
+    eprintln!("Error: {}", error);
+    process::exit(1);
+}
+
+/// Unwraps the given result of setting up OpenCL (i.e. finding the device or
+/// creating an engine). If it is an error, we either exit (if the OpenCL engines
+/// are required) or warn that we skip the given engines.
+fn opencl_or_skip<T>(result: Result<T, Error>, required: bool, skipped: &str) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(e) if required => fail(e),
+        Err(e) => {
+            eprintln!("Warning: {}, skipping {}", e, skipped);
+            None
+        },
+    }
+}
+
+/// Copies the database to the GPU for an OpenCL engine. If this fails,
+/// the engine still works (copying the database for every chunk).
+fn register_database(result: Result<(), Error>) {
+    if let Err(e) = result {
+        eprintln!("Warning: Could not keep the database on the GPU: {}", e);
+    }
+}
+
+fn print_devices() -> Result<(), Error> {
+    let platforms = opencl_platforms();
+    if platforms.is_empty() {
+        println!("No OpenCL platforms found (is an OpenCL runtime installed?)");
+    }
+
+    for (platform_index, platform) in platforms.into_iter().enumerate() {
+        println!("{}", pretty_box(format!("Platform {}: {}", platform_index, platform.name()?)));
+        println!("Vendor: {}, version: {}", platform.vendor()?, platform.version()?);
+
+        // The devices are selected by their index among those of the same type
+        let devices = Device::list_all(platform)?;
+        for (i, &device) in devices.iter().enumerate() {
+            let device_type = OpenCLDeviceType::of(device);
+            let index = devices[..i].iter().filter(|&&other| OpenCLDeviceType::of(other) == device_type).count();
+            println!();
+            println!("{} {}: {} ({})", device_type, index, device.name()?, device.vendor()?);
+            println!("  Version: {}", device.info(DeviceInfo::Version)?);
+            println!("  Compute units: {}", device.info(DeviceInfo::MaxComputeUnits)?);
+            println!("  Max work-group size: {}", device.max_wg_size()?);
+            println!("  Global memory: {} bytes", device.info(DeviceInfo::GlobalMemSize)?);
+            println!("  Local memory: {} bytes", device.info(DeviceInfo::LocalMemSize)?);
+            println!("  Available: {}", device.is_available()?);
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
 #[derive(Parser)]
 #[clap(version, about)]
 struct Cli {
     #[clap(subcommand)]
     command: Command,
 
-    /// The index of the GPU to use (for OpenCL).
+    /// The index of the GPU (or, more generally, of the device among those
+    /// of the selected type) to use (for OpenCL).
     #[clap(short, long, default_value_t = 0)]
     gpu_index: usize,
+
+    /// The index of the OpenCL platform to use (see the devices command).
+    #[clap(long, default_value_t = 0)]
+    platform_index: usize,
+
+    /// The type of OpenCL devices to choose from (gpu, cpu, accelerator or all).
+    #[clap(long, default_value = "gpu")]
+    device_type: OpenCLDeviceType,
+
+    /// The SIMD instruction set to use (scalar, sse2 or avx2), defaults
+    /// to the widest one supported by the CPU.
+    #[clap(long)]
+    instruction_set: Option<InstructionSet>,
+
+    /// The edge length of the tiles (for the tiled engine).
+    #[clap(long, default_value_t = TiledEngine::DEFAULT_TILE_SIZE)]
+    tile_size: usize,
+}
+
+#[derive(Args)]
+struct ScoringArgs {
+    /// The penalty for opening a gap.
+    #[clap(long, default_value_t = 2)]
+    gap_open: i16,
+
+    /// The penalty for extending a gap.
+    #[clap(long, default_value_t = 2)]
+    gap_extend: i16,
+
+    /// The score for aligning two equal residues (if no matrix is used).
+    #[clap(long = "match", default_value_t = 3, allow_hyphen_values = true)]
+    match_score: i16,
+
+    /// The score for aligning two different residues (if no matrix is used).
+    #[clap(long = "mismatch", default_value_t = -3, allow_hyphen_values = true)]
+    mismatch_score: i16,
+
+    /// The substitution matrix to use, either the name of a built-in
+    /// matrix (e.g. BLOSUM62 or PAM250) or the path to a matrix file
+    /// in the NCBI format.
+    #[clap(long, conflicts_with_all = &["match-score", "mismatch-score"])]
+    matrix: Option<String>,
+
+    /// The alignment mode (local, global, semi-global or overlap).
+    #[clap(long, default_value = "local")]
+    mode: AlignmentMode,
+
+    /// Whether leading gaps in the database (skipping the start of the query) are free.
+    #[clap(long)]
+    free_database_leading: bool,
+
+    /// Whether trailing gaps in the database (skipping the end of the query) are free.
+    #[clap(long)]
+    free_database_trailing: bool,
+
+    /// Whether leading gaps in the query (skipping the start of the database) are free.
+    #[clap(long)]
+    free_query_leading: bool,
+
+    /// Whether trailing gaps in the query (skipping the end of the database) are free.
+    #[clap(long)]
+    free_query_trailing: bool,
+}
+
+impl ScoringArgs {
+    /// Creates the scoring scheme, loading the substitution matrix if needed.
+    fn scoring_scheme(&self, alphabet: Alphabet) -> ScoringScheme {
+        let matrix = match &self.matrix {
+            Some(name) => SubstitutionMatrix::builtin(name, alphabet)
+                .unwrap_or_else(|| {
+                    let src = fs::read_to_string(name).unwrap_or_else(|_| fail(format!(
+                        "Could not find substitution matrix '{}' (built-in matrices are: {})",
+                        name, SubstitutionMatrix::builtin_names().collect::<Vec<_>>().join(", ")
+                    )));
+                    SubstitutionMatrix::parse(&src, alphabet)
+                })
+                .unwrap_or_else(|e| fail(format!("Could not parse substitution matrix '{}': {}", name, e))),
+            None => SubstitutionMatrix::uniform(alphabet, self.match_score, self.mismatch_score),
+        };
+        ScoringScheme::new(self.gap_open, self.gap_extend, matrix)
+    }
+
+    /// Creates the alignment mode, adding the free end gaps (if any).
+    fn alignment_mode(&self) -> AlignmentMode {
+        let flags = FreeEndGaps {
+            database_leading: self.free_database_leading,
+            database_trailing: self.free_database_trailing,
+            query_leading: self.free_query_leading,
+            query_trailing: self.free_query_trailing,
+        };
+        if flags == FreeEndGaps::default() {
+            return self.mode;
+        }
+        let gaps = self.mode.free_end_gaps().unwrap_or_else(|| fail("Free end gaps require a (semi-)global mode"));
+        AlignmentMode::SemiGlobal(FreeEndGaps {
+            database_leading: gaps.database_leading || flags.database_leading,
+            database_trailing: gaps.database_trailing || flags.database_trailing,
+            query_leading: gaps.query_leading || flags.query_leading,
+            query_trailing: gaps.query_trailing || flags.query_trailing,
+        })
+    }
 }
 
 #[derive(Subcommand)]
 enum Command {
+    /// Lists the OpenCL platforms and devices along with their properties.
+    Devices,
     /// Runs the engines once on a pair of sequences.
     Run {
         /// The database sequence.
@@ -77,6 +336,13 @@ enum Command {
         /// The query sequence.
         #[clap(default_value = "GGTTGACTA")]
         query: String,
+
+        /// The alphabet of the sequences (dna, rna, protein or iupac-dna).
+        #[clap(short, long, default_value = "dna")]
+        alphabet: Alphabet,
+
+        #[clap(flatten)]
+        scoring: ScoringArgs,
     },
     /// Benchmarks the different engines.
     Bench {
@@ -92,6 +358,10 @@ enum Command {
         #[clap(short, long, default_value_t = 1)]
         repeats: usize,
 
+        /// The alphabet of the sequences (dna, rna, protein or iupac-dna).
+        #[clap(short, long, default_value = "protein")]
+        alphabet: Alphabet,
+
         /// Whether to benchmark the naive (CPU) engine.
         #[clap(long)]
         naive: bool,
@@ -108,9 +378,32 @@ enum Command {
         #[clap(long)]
         opencl_diagonal: bool,
 
-        /// Whether to benchmark the cache-optimized diagonal OpenCL (GPU) engine.
+        /// Whether to benchmark the inter-task OpenCL (GPU) engine.
         #[clap(long)]
-        optimized_opencl_diagonal: bool,
+        opencl_inter_task: bool,
+
+        /// Whether to benchmark the linear-space Hirschberg (CPU) engine.
+        #[clap(long)]
+        hirschberg: bool,
+
+        /// Whether to benchmark the striped SIMD (CPU) engine.
+        #[clap(long)]
+        striped: bool,
+
+        /// Whether to benchmark the inter-sequence SIMD (CPU) engine.
+        #[clap(long)]
+        inter_sequence: bool,
+
+        /// Whether to benchmark the tiled wavefront (CPU) engine.
+        #[clap(long)]
+        tiled: bool,
+
+        /// Whether to only compute the scores (and ends) of the alignments.
+        #[clap(long)]
+        score_only: bool,
+
+        #[clap(flatten)]
+        scoring: ScoringArgs,
     },
 }
 
@@ -118,63 +411,134 @@ fn main() {
     // Parse CLI args
     let cli = Cli::parse();
 
-    // Create engines
-    let naive_engine = NaiveEngine;
-    let diagonal_engine = DiagonalEngine;
-    let optimized_diagonal_engine = OptimizedDiagonalEngine;
-    let opencl_diagonal_engine = OpenCLDiagonalEngine::new(cli.gpu_index);
-    let optimized_opencl_diagonal_engine = OptimizedOpenCLDiagonalEngine::new(cli.gpu_index);
+    // Fetch the scoring parameters (unless we only list the devices)
+    let (scoring, mode) = match &cli.command {
+        Command::Run { scoring, alphabet, .. } | Command::Bench { scoring, alphabet, .. } => (scoring.scoring_scheme(*alphabet), scoring.alignment_mode()),
+        Command::Devices => {
+            if let Err(e) = print_devices() {
+                fail(e);
+            }
+            return;
+        },
+    };
+
+    // The engines are only created once they are needed (and the
+    // OpenCL engines only if the selected device exists)
+    let instruction_set = cli.instruction_set.unwrap_or_else(InstructionSet::detect);
+    let selector = OpenCLDeviceSelector { platform_index: cli.platform_index, device_type: cli.device_type, device_index: cli.gpu_index };
 
     match cli.command {
-        Command::Run { database, query } => {
-            let database = database.parse().unwrap();
-            let query = query.parse().unwrap();
-
-            run(&naive_engine, &database, &query);
-            run(&diagonal_engine, &database, &query);
-            run(&optimized_diagonal_engine, &database, &query);
-            run(&opencl_diagonal_engine, &database, &query);
-            run(&optimized_opencl_diagonal_engine, &database, &query);
+        Command::Devices => unreachable!(),
+        Command::Run { database, query, alphabet, .. } => {
+            let parse = |name: &str, raw: String| Sequence::parse(name, alphabet, raw.as_bytes())
+                .unwrap_or_else(|error| fail(Error::InvalidResidues { sequence: name.to_owned(), error }));
+            let database = parse("Database", database);
+            let query = parse("Query", query);
+
+            run(&NaiveEngine::new(scoring.clone(), mode), &database, &query);
+            run(&DiagonalEngine::new(scoring.clone(), mode), &database, &query);
+            run(&OptimizedDiagonalEngine::new(scoring.clone(), mode), &database, &query);
+            if let Some(opencl) = opencl_or_skip(selector.select(), false, "the OpenCL engines") {
+                if let Some(engine) = opencl_or_skip(OpenCLDiagonalEngine::new(opencl, scoring.clone(), mode), false, "the OpenCL diagonal engine") {
+                    run(&engine, &database, &query);
+                }
+                if let Some(engine) = opencl_or_skip(OpenCLInterTaskEngine::new(opencl, scoring.clone(), mode), false, "the OpenCL inter-task engine") {
+                    run(&engine, &database, &query);
+                }
+            }
+            run(&HirschbergEngine::new(scoring.clone(), mode), &database, &query);
+            run(&StripedEngine::new(scoring.clone(), mode, instruction_set).unwrap_or_else(|e| fail(e)), &database, &query);
+            run(&InterSequenceEngine::new(scoring.clone(), mode, instruction_set).unwrap_or_else(|e| fail(e)), &database, &query);
+            run(&TiledEngine::new(scoring, mode, cli.tile_size).unwrap_or_else(|e| fail(e)), &database, &query);
         },
-        Command::Bench { path, number, repeats, naive, diagonal, opencl_diagonal, optimized_diagonal, optimized_opencl_diagonal } => {
-            let default = !naive && !diagonal && !optimized_diagonal && !opencl_diagonal && !optimized_opencl_diagonal;
+        Command::Bench { path, number, repeats, alphabet, naive, diagonal, opencl_diagonal, optimized_diagonal, opencl_inter_task, hirschberg, striped, inter_sequence, tiled, score_only, .. } => {
+            let default = !naive && !diagonal && !optimized_diagonal && !opencl_diagonal && !opencl_inter_task && !hirschberg && !striped && !inter_sequence && !tiled;
             // Read a subset of the sequences from the downloaded dataset
-            let file = File::open(path).expect("Could not open dataset (did you specify --path?)");
-            let mut reader = FastaReader::new(BufReader::new(file)).map(|x| x.cycle(repeats));
-            let database = reader.next().unwrap();
-            let queries = reader.take(number).collect();
+            // Invalid queries are skipped (with a warning)
+            let file = File::open(path).unwrap_or_else(|e| fail(format!("Could not open dataset (did you specify --path?): {}", e)));
+            let mut reader = FastaReader::new(BufReader::new(file), alphabet).map(|x| x.map(|x| x.cycle(repeats)));
+            let database = match reader.next() {
+                Some(database) => database.unwrap_or_else(|e| fail(e)),
+                None => fail("The dataset contains no sequences"),
+            };
+            let queries: Vec<_> = reader
+                .filter_map(|query| query.map_err(|e| eprintln!("Warning: {}, skipping it", e)).ok())
+                .take(number)
+                .collect();
+
+            // Find the device for the OpenCL engines (if any of them are selected).
+            // If they were selected explicitly, a missing device (or failing to
+            // create them) is an error, otherwise we skip them.
+            let required = !default;
+            let opencl = if opencl_diagonal || opencl_inter_task || default {
+                opencl_or_skip(selector.select(), required, "the OpenCL engines")
+            } else {
+                None
+            };
 
             // Use asserters to verify that engines yield the same result.
-            // Note that the optimized diagonal engines use a different
-            // asserter since they may yield different solutions during
+            // Note that the optimized diagonal engine uses a different
+            // asserter since it may yield different solutions during
             // the traceback stage if there are multiple (equivalent) maximums.
             let mut asserter = EqualAsserter::new();
             let mut optimized_asserter = EqualAsserter::new();
 
             // Benchmark the naive (CPU) engine
             if naive || default {
-                asserter.feed(bench_sequential(&naive_engine, &database, &queries));
-                asserter.feed(bench_parallel(&naive_engine, &database, &queries));
+                let naive_engine = NaiveEngine::new(scoring.clone(), mode);
+                asserter.feed(bench_sequential(&naive_engine, &database, &queries, score_only));
+                asserter.feed(bench_parallel(&naive_engine, &database, &queries, score_only));
             }
 
             // Benchmark the diagonal (CPU) engine
             if diagonal || default {
-                asserter.feed(bench_parallel(&diagonal_engine, &database, &queries));
+                asserter.feed(bench_parallel(&DiagonalEngine::new(scoring.clone(), mode), &database, &queries, score_only));
             }
 
             // Benchmark the cache-optimized diagonal (CPU) engine
             if optimized_diagonal || default {
-                optimized_asserter.feed(bench_parallel(&optimized_diagonal_engine, &database, &queries));
+                optimized_asserter.feed(bench_parallel(&OptimizedDiagonalEngine::new(scoring.clone(), mode), &database, &queries, score_only));
             }
 
             // Benchmark the OpenCL diagonal (GPU) engine
-            if opencl_diagonal || default {
-                asserter.feed(bench_parallel(&opencl_diagonal_engine, &database, &queries));
+            if let Some(opencl) = opencl.filter(|_| opencl_diagonal || default) {
+                if let Some(engine) = opencl_or_skip(OpenCLDiagonalEngine::new(opencl, scoring.clone(), mode), required, "the OpenCL diagonal engine") {
+                    register_database(engine.register_database(slice::from_ref(&database)));
+                    asserter.feed(bench_parallel(&engine, &database, &queries, score_only));
+                }
+            }
+
+            // Benchmark the inter-task OpenCL (GPU) engine, which scores many pairs per launch
+            if let Some(opencl) = opencl.filter(|_| opencl_inter_task) {
+                if let Some(engine) = opencl_or_skip(OpenCLInterTaskEngine::new(opencl, scoring.clone(), mode), required, "the OpenCL inter-task engine") {
+                    register_database(engine.register_database(slice::from_ref(&database)));
+                    asserter.feed(bench_parallel(&engine, &database, &queries, score_only));
+                }
+            }
+
+            // Benchmark the linear-space Hirschberg (CPU) engine. Since its alignments
+            // may differ if there are multiple (equivalent) maximums, we only compare
+            // the results of the score-only mode (which are the same for all engines).
+            if hirschberg {
+                let results = bench_parallel(&HirschbergEngine::new(scoring.clone(), mode), &database, &queries, score_only);
+                if score_only {
+                    asserter.feed(results);
+                }
+            }
+
+            // Benchmark the striped SIMD (CPU) engine
+            if striped || default {
+                asserter.feed(bench_parallel(&StripedEngine::new(scoring.clone(), mode, instruction_set).unwrap_or_else(|e| fail(e)), &database, &queries, score_only));
+            }
+
+            // Benchmark the inter-sequence SIMD (CPU) engine, which aligns many queries at once
+            if inter_sequence || default {
+                asserter.feed(bench_search(&InterSequenceEngine::new(scoring.clone(), mode, instruction_set).unwrap_or_else(|e| fail(e)), &database, &queries, score_only));
             }
 
-            // Benchmark the cache-optimized OpenCL diagonal (GPU) engine
-            if optimized_opencl_diagonal {
-                optimized_asserter.feed(bench_parallel(&optimized_opencl_diagonal_engine, &database, &queries));
+            // Benchmark the tiled wavefront (CPU) engine
+            if tiled || default {
+                asserter.feed(bench_parallel(&TiledEngine::new(scoring, mode, cli.tile_size).unwrap_or_else(|e| fail(e)), &database, &queries, score_only));
             }
         },
     }
@@ -1,17 +1,41 @@
-use std::{fmt, ops::Index, str::FromStr};
+use std::{fmt, ops::Index};
 
-/// A (named) nucleid sequence.
+use crate::alphabet::{Alphabet, InvalidResiduesError};
+
+/// A (named) nucleid or protein sequence.
 #[derive(PartialEq, Eq, Clone)]
 pub struct Sequence {
     pub name: String,
-    pub raw: Vec<u8>,
+    pub alphabet: Alphabet,
+    /// The residues, encoded as indices into the alphabet.
+    pub residues: Vec<u8>,
+}
+
+/// An operation in an alignment. Following the CIGAR
+/// conventions, insertions and deletions are relative
+/// to the database (i.e. the reference).
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Operation {
+    /// Two equal residues are aligned.
+    Match,
+    /// Two different residues are aligned.
+    Mismatch,
+    /// A residue of the query is aligned to a gap in the database.
+    Insertion,
+    /// A residue of the database is aligned to a gap in the query.
+    Deletion,
 }
 
-/// An alignment on a nucleid sequence.
+/// An alignment on a nucleid or protein sequence.
 #[derive(PartialEq, Eq, Clone)]
 pub struct AlignedSequence<'a> {
     pub sequence: &'a Sequence,
-    pub indices: Vec<usize>,
+    /// The index of the residue in each column (or none for a gap).
+    pub indices: Vec<Option<usize>>,
+    /// The index of the first aligned residue.
+    pub start: usize,
+    /// The index after the last aligned residue.
+    pub end: usize,
 }
 
 /// An alignment of two sequences.
@@ -19,56 +43,106 @@ pub struct AlignedSequence<'a> {
 pub struct AlignedPair<'a> {
     pub database: AlignedSequence<'a>,
     pub query: AlignedSequence<'a>,
+    /// The operations that transform the aligned database
+    /// subsequence into the aligned query subsequence.
+    pub operations: Vec<Operation>,
+    /// The score of the alignment.
+    pub score: i32,
+}
+
+/// The score of an alignment and where it ends, i.e. the
+/// result of aligning two sequences without a traceback.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct AlignmentScore {
+    /// The score of the alignment.
+    pub score: i32,
+    /// The index after the last aligned residue of the database.
+    pub database_end: usize,
+    /// The index after the last aligned residue of the query.
+    pub query_end: usize,
 }
 
 impl Sequence {
-    pub fn new(name: &str, raw: Vec<u8>) -> Self {
-        Self { name: name.to_owned(), raw }
+    pub fn new(name: &str, alphabet: Alphabet, residues: Vec<u8>) -> Self {
+        Self { name: name.to_owned(), alphabet, residues }
+    }
+
+    /// Parses a sequence from raw (ASCII) residues.
+    pub fn parse(name: &str, alphabet: Alphabet, raw: &[u8]) -> Result<Self, InvalidResiduesError> {
+        Ok(Self::new(name, alphabet, alphabet.encode(raw)?))
     }
 
     /// The length of the sequence.
     pub fn len(&self) -> usize {
-        self.raw.len()
+        self.residues.len()
     }
 
     /// Cycles the sequence n times.
     pub fn cycle(self, n: usize) -> Self {
         let len = self.len();
-        Self { name: self.name, raw: self.raw.into_iter().cycle().take(n * len).collect() }
+        Self { residues: self.residues.into_iter().cycle().take(n * len).collect(), ..self }
     }
 }
 
-impl FromStr for Sequence {
-    type Err = ();
+impl Index<usize> for Sequence {
+    type Output = u8;
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Self::new("Parsed", s.as_bytes().iter().map(|&x| x).collect()))
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.residues[index]
     }
 }
 
-impl Index<usize> for Sequence {
-    type Output = u8;
+impl Operation {
+    /// Whether the operation consumes a residue of the database.
+    pub fn consumes_database(self) -> bool {
+        self != Operation::Insertion
+    }
 
-    fn index(&self, index: usize) -> &Self::Output {
-        &self.raw[index]
+    /// Whether the operation consumes a residue of the query.
+    pub fn consumes_query(self) -> bool {
+        self != Operation::Deletion
     }
 }
 
 impl<'a> AlignedSequence<'a> {
-    pub fn new(sequence: &'a Sequence, indices: Vec<usize>) -> Self {
-        Self { sequence, indices }
+    /// Derives the aligned sequence from the given operations, starting
+    /// at the given index. The predicate determines which operations
+    /// consume a residue of this sequence (the others yield gaps).
+    pub fn from_operations(sequence: &'a Sequence, start: usize, operations: &[Operation], consumes: impl Fn(Operation) -> bool) -> Self {
+        let mut end = start;
+        let indices = operations.iter().map(|&op| {
+            if consumes(op) {
+                end += 1;
+                Some(end - 1)
+            } else {
+                None
+            }
+        }).collect();
+        Self { sequence, indices, start, end }
     }
 }
 
 impl<'a> AlignedPair<'a> {
-    pub fn new(database: AlignedSequence<'a>, query: AlignedSequence<'a>) -> Self {
-        Self { database, query }
+    /// Creates an alignment from the operations that start at
+    /// the given indices in the database and query.
+    pub fn new(database: &'a Sequence, query: &'a Sequence, database_start: usize, query_start: usize, operations: Vec<Operation>, score: i32) -> Self {
+        Self {
+            database: AlignedSequence::from_operations(database, database_start, &operations, Operation::consumes_database),
+            query: AlignedSequence::from_operations(query, query_start, &operations, Operation::consumes_query),
+            operations,
+            score,
+        }
+    }
+
+    /// The length of the alignment (including gaps).
+    pub fn len(&self) -> usize {
+        self.operations.len()
     }
 }
 
 impl fmt::Display for Sequence {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", String::from_utf8(self.raw.clone()).unwrap())
+        write!(f, "{}", String::from_utf8(self.alphabet.decode(&self.residues)).unwrap())
     }
 }
 
@@ -80,15 +154,11 @@ impl fmt::Debug for Sequence {
 
 impl<'a> fmt::Display for AlignedSequence<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut last: Option<usize> = None;
         for &i in &self.indices {
-            let c = self.sequence.raw[i] as char;
-            if last == Some(i) {
-                write!(f, "-")?;
-            } else {
-                write!(f, "{}", c)?;
+            match i {
+                Some(i) => write!(f, "{}", self.sequence.alphabet.decode_residue(self.sequence[i]) as char)?,
+                None => write!(f, "-")?,
             }
-            last = Some(i);
         }
         Ok(())
     }
@@ -102,7 +172,13 @@ impl<'a> fmt::Debug for AlignedSequence<'a> {
 
 impl<'a> fmt::Display for AlignedPair<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "(D: {}, Q: {})", self.database, self.query)
+        write!(f, "(D: {}, Q: {}, score: {})", self.database, self.query, self.score)
+    }
+}
+
+impl fmt::Display for AlignmentScore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(score: {}, database end: {}, query end: {})", self.score, self.database_end, self.query_end)
     }
 }
 
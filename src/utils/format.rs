@@ -6,7 +6,8 @@ pub fn pretty_box(s: impl Borrow<str>) -> String {
     format!("┌{}┐\n│ {} │\n└{}┘", gutter, s.borrow(), gutter)
 }
 
-/// Formats a matrix of the given dimensions.
+/// Formats a matrix of the given dimensions (useful for debugging).
+#[allow(dead_code)]
 pub fn pretty_matrix<T>(mat: &[T], width: usize) -> String where T: Debug {
     let mut s = Vec::new();
     for i in (0..mat.len()).step_by(width) {
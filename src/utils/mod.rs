@@ -1,7 +1,9 @@
 mod assert;
 mod format;
+mod pool;
 mod slice;
 
 pub use assert::*;
 pub use format::*;
+pub use pool::*;
 pub use slice::*;
@@ -0,0 +1,69 @@
+use std::{any::Any, convert::Infallible, ops::{Deref, DerefMut}, sync::Mutex};
+
+/// A pool of reusable objects (e.g. scratch buffers) of arbitrary types,
+/// which lets (parallel) callers take an object rather than allocating a
+/// new one every time. Since every caller takes its own object, the
+/// pool holds at most as many objects of a type as there are threads.
+#[derive(Default)]
+pub struct Pool {
+    items: Mutex<Vec<Box<dyn Any + Send>>>,
+}
+
+impl Pool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes an object of the given type from the pool, or creates one
+    /// if there is none. The object is returned to the pool once the
+    /// returned guard is dropped.
+    pub fn take<T: Send + 'static>(&self, create: impl FnOnce() -> T) -> Pooled<'_, T> {
+        match self.try_take(|| Ok::<_, Infallible>(create())) {
+            Ok(item) => item,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Takes an object like `take`, but creating it may fail,
+    /// in which case the error is returned.
+    pub fn try_take<T: Send + 'static, E>(&self, create: impl FnOnce() -> Result<T, E>) -> Result<Pooled<'_, T>, E> {
+        let item = {
+            let mut items = self.items.lock().unwrap();
+            items.iter().position(|item| item.is::<T>())
+                .map(|i| items.swap_remove(i).downcast().unwrap())
+        };
+        let item = match item {
+            Some(item) => item,
+            None => Box::new(create()?),
+        };
+        Ok(Pooled { pool: self, item: Some(item) })
+    }
+}
+
+/// An object taken from a pool, which is returned once dropped.
+pub struct Pooled<'a, T: Send + 'static> {
+    pool: &'a Pool,
+    item: Option<Box<T>>,
+}
+
+impl<'a, T: Send + 'static> Deref for Pooled<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.item.as_ref().unwrap()
+    }
+}
+
+impl<'a, T: Send + 'static> DerefMut for Pooled<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.item.as_mut().unwrap()
+    }
+}
+
+impl<'a, T: Send + 'static> Drop for Pooled<'a, T> {
+    fn drop(&mut self) {
+        if let Some(item) = self.item.take() {
+            self.pool.items.lock().unwrap().push(item);
+        }
+    }
+}
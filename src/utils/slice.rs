@@ -19,6 +19,7 @@ impl<'a, T> UnsafeSlice<'a, T> {
     }
 
     /// Fetches the slice.
+    #[allow(dead_code)]
     pub unsafe fn slice(&self) -> &'a [T] {
         &*(self.slice as *const [UnsafeCell<T>] as *mut [T])
     }